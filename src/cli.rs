@@ -0,0 +1,486 @@
+use crate::auth;
+use crate::error;
+use crate::history::ExportFormat;
+use crate::metadata::MetadataTag;
+use crate::network::HttpVersion;
+use crate::output::OutputFormat;
+use crate::progress::ProgressMode;
+use crate::retry;
+use crate::sniff::ImageType;
+use crate::transform::TargetFormat;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None, after_help = error::EXIT_CODES_HELP)]
+#[command(subcommand_negates_reqs = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Configuration file to load on top of the discovered ones
+    #[arg(short, long, value_name = "PATH", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Profile to use from the configuration [default: $ANARCHIC_PROFILE, then default_profile]
+    #[arg(short, long, value_name = "NAME", global = true)]
+    pub profile: Option<String>,
+
+    /// Base URL of the service, overriding the configured endpoint
+    #[arg(short, long, global = true)]
+    pub url: Option<String>,
+
+    #[command(flatten)]
+    pub network: NetworkArgs,
+
+    // Uploading is what happens when no subcommand is given
+    #[command(flatten)]
+    pub upload: UploadArgs,
+}
+
+impl Cli {
+    /// Parses the command line. Upload options only apply without a subcommand, so they are
+    /// rejected before one instead of being ignored; a subcommand after file names, which
+    /// clap takes for another file, is rejected as well.
+    pub fn parse_args() -> Cli {
+        let mut command = Cli::command();
+        let matches = command.get_matches_mut();
+        let upload_args = UploadArgs::augment_args(clap::Command::new("upload"));
+        match matches.subcommand_name() {
+            Some(subcommand) => {
+                let given = upload_args
+                    .get_arguments()
+                    .find(|arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine));
+                if let Some(arg) = given {
+                    let name = arg.get_long().map_or_else(|| arg.get_id().to_string(), |long| format!("--{}", long));
+                    let message = format!("{} only applies to uploads, it cannot be given before `{}`", name, subcommand);
+                    command.error(ErrorKind::ArgumentConflict, message).exit();
+                }
+            }
+            None => {
+                let file_paths = matches.get_many::<String>("file_paths").into_iter().flatten();
+                let subcommand = file_paths
+                    .filter(|path| !Path::new(path).exists())
+                    .find_map(|path| command.find_subcommand(path).map(|subcommand| subcommand.get_name().to_string()));
+                if let Some(subcommand) = subcommand {
+                    let message = format!("`{}` is a subcommand, it has to come before any file names and upload options", subcommand);
+                    command.error(ErrorKind::ArgumentConflict, message).exit();
+                }
+            }
+        }
+        Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit())
+    }
+}
+
+// Written by hand so a password in `--url` stays out of the debug log
+impl fmt::Debug for Cli {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cli")
+            .field("command", &self.command)
+            .field("config", &self.config)
+            .field("profile", &self.profile)
+            .field("url", &self.url.as_deref().map(auth::redact_url))
+            .field("network", &self.network)
+            .field("upload", &self.upload)
+            .finish()
+    }
+}
+
+// Proxy, TLS, timeout and connection settings, overriding the `network` section of the configuration
+#[derive(Args)]
+pub struct NetworkArgs {
+    /// Proxy for all requests: an http://, https://, socks5:// or socks5h:// URL
+    #[arg(long, value_name = "URL", global = true)]
+    pub proxy: Option<String>,
+
+    /// Hosts to reach without the proxy, comma-separated; needs a proxy from --proxy or the configuration
+    #[arg(long, value_name = "HOSTS", global = true)]
+    pub no_proxy: Option<String>,
+
+    /// PEM file with CA certificates to trust besides the system ones
+    #[arg(long, value_name = "PATH", global = true)]
+    pub ca_bundle: Option<PathBuf>,
+
+    /// Client certificate for mutual TLS, as PEM or PKCS#12 (its password is read from the configuration)
+    #[arg(long, value_name = "PATH", global = true)]
+    pub client_cert: Option<PathBuf>,
+
+    /// PEM private key of --client-cert, when it is not in the same file
+    #[arg(long, value_name = "PATH", global = true)]
+    pub client_key: Option<PathBuf>,
+
+    /// Do not verify TLS certificates; only meant for testing
+    #[arg(long, global = true)]
+    pub insecure: bool,
+
+    /// Only accept servers whose public key has this SHA-256 (sha256/<base64>); may be repeated
+    #[arg(long = "pin", value_name = "PIN", global = true)]
+    pub spki_pins: Vec<String>,
+
+    /// Seconds to wait for a connection to the server; 0 waits forever [default: 30]
+    #[arg(long, value_name = "SECS", global = true)]
+    pub connect_timeout: Option<u64>,
+
+    /// Seconds a whole request, upload included, may take; 0 waits forever [default: no limit]
+    #[arg(long, value_name = "SECS", global = true)]
+    pub request_timeout: Option<u64>,
+
+    /// Seconds to wait for the next bytes of a response; 0 waits forever [default: 120]
+    #[arg(long, value_name = "SECS", global = true)]
+    pub read_idle_timeout: Option<u64>,
+
+    /// HTTP version to speak [default: auto]
+    #[arg(long, value_enum, value_name = "VERSION", global = true)]
+    pub http_version: Option<HttpVersion>,
+
+    /// Seconds between TCP keep-alive probes on open connections
+    #[arg(long, value_name = "SECS", global = true)]
+    pub tcp_keepalive: Option<u64>,
+
+    /// Seconds an idle connection is kept open for reuse [default: 90]
+    #[arg(long, value_name = "SECS", global = true)]
+    pub pool_idle_timeout: Option<u64>,
+
+    /// Idle connections kept open per host; 0 opens a new connection for each request
+    #[arg(long, value_name = "N", global = true)]
+    pub pool_max_idle_per_host: Option<usize>,
+}
+
+// Written by hand so proxy credentials stay out of the debug log
+impl fmt::Debug for NetworkArgs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Taken apart so a new field cannot be left out unnoticed
+        let NetworkArgs {
+            proxy,
+            no_proxy,
+            ca_bundle,
+            client_cert,
+            client_key,
+            insecure,
+            spki_pins,
+            connect_timeout,
+            request_timeout,
+            read_idle_timeout,
+            http_version,
+            tcp_keepalive,
+            pool_idle_timeout,
+            pool_max_idle_per_host,
+        } = self;
+        f.debug_struct("NetworkArgs")
+            .field("proxy", &proxy.as_deref().map(auth::redact_url))
+            .field("no_proxy", no_proxy)
+            .field("ca_bundle", ca_bundle)
+            .field("client_cert", client_cert)
+            .field("client_key", client_key)
+            .field("insecure", insecure)
+            .field("spki_pins", spki_pins)
+            .field("connect_timeout", connect_timeout)
+            .field("request_timeout", request_timeout)
+            .field("read_idle_timeout", read_idle_timeout)
+            .field("http_version", http_version)
+            .field("tcp_keepalive", tcp_keepalive)
+            .field("pool_idle_timeout", pool_idle_timeout)
+            .field("pool_max_idle_per_host", pool_max_idle_per_host)
+            .finish()
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Upload files (the default when no subcommand is given)
+    Upload(UploadArgs),
+
+    /// Delete an uploaded image, using the delete token recorded in the history
+    Delete {
+        /// Id or URL of the image, or @N for entry N of `history list`
+        target: String,
+    },
+
+    /// Show what the service knows about an uploaded image
+    Info {
+        /// Id of the image
+        id: String,
+
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+
+    /// List the images uploaded to the service
+    List {
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+
+    /// Watch a directory and upload each image written to it, once writes have settled
+    Watch(WatchArgs),
+
+    /// Download an uploaded image
+    Download {
+        /// Id of the image, or its URL
+        image: String,
+
+        /// Where to save the image; `-` writes it to stdout [default: the name in the URL]
+        #[arg(short = 'O', long, value_name = "PATH")]
+        dest: Option<PathBuf>,
+
+        /// Decrypt an upload made with `--encrypt`, using the key after `#key=` in the URL
+        #[arg(long)]
+        decrypt: bool,
+    },
+
+    /// Browse the local record of uploads
+    History {
+        #[command(subcommand)]
+        command: HistoryCommand,
+    },
+
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+
+    /// Store a token for the selected profile, read from a prompt or stdin
+    Login {
+        /// Exchange this username and a password for a token at the profile's login endpoint
+        #[arg(long)]
+        username: Option<String>,
+    },
+
+    /// Forget the token stored for the selected profile
+    Logout,
+
+    /// Check the configuration, credentials and connection to the service
+    Doctor,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// List the profiles defined in the configuration
+    Profiles,
+
+    /// List the configuration files that were loaded, lowest precedence first
+    Paths,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum HistoryCommand {
+    /// List recorded uploads, oldest first
+    List {
+        #[command(flatten)]
+        filter: HistoryFilterArgs,
+    },
+
+    /// List recorded uploads whose source path, URL or id contain all the words, ignoring case
+    Search {
+        #[arg(required = true)]
+        words: Vec<String>,
+
+        #[command(flatten)]
+        filter: HistoryFilterArgs,
+    },
+
+    /// Show everything recorded about one upload
+    Show {
+        /// Number of the entry as shown by `history list` (also written @N), or its URL
+        entry: String,
+    },
+
+    /// Print recorded uploads in a format for other tools
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Jsonl)]
+        format: ExportFormat,
+
+        #[command(flatten)]
+        filter: HistoryFilterArgs,
+    },
+}
+
+// Filters shared by the history commands
+#[derive(Args, Debug)]
+pub struct HistoryFilterArgs {
+    /// Only uploads made on or after this date (YYYY-MM-DD, or an RFC 3339 time)
+    #[arg(long, value_name = "DATE")]
+    pub since: Option<String>,
+
+    /// Only uploads made on or before this date (YYYY-MM-DD, or an RFC 3339 time)
+    #[arg(long, value_name = "DATE")]
+    pub until: Option<String>,
+
+    /// Only uploads made with this profile
+    #[arg(long, value_name = "NAME")]
+    pub from_profile: Option<String>,
+
+    /// Only uploads whose file name matches this glob, e.g. '*.png'
+    #[arg(long, value_name = "GLOB")]
+    pub name: Option<String>,
+}
+
+// How results are printed, shared by the commands that print images; a doc comment
+// here would replace the about text of the commands it is flattened into
+#[derive(Args, Debug)]
+pub struct OutputArgs {
+    /// How to print each image [default: url, or template when --template is given]
+    #[arg(short, long, value_enum, value_name = "FORMAT")]
+    pub output: Option<OutputFormat>,
+
+    /// Template for --output template, e.g. '{url} {width}x{height}'; {name} is the local file name
+    #[arg(long, value_name = "TEMPLATE")]
+    pub template: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct UploadArgs {
+    /// Paths or glob patterns of the image files to upload; `-` reads an image from stdin
+    #[arg(required = true)]
+    pub file_paths: Vec<String>,
+
+    /// File name to send for the image read from stdin [default: stdin, with the extension of its content]
+    #[arg(long, value_name = "NAME")]
+    pub filename: Option<String>,
+
+    /// Maximum number of uploads running at the same time
+    #[arg(short, long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+    pub jobs: u16,
+
+    /// Upload the contents of directories, descending into subdirectories
+    #[arg(short, long)]
+    pub recursive: bool,
+
+    /// Only upload files whose path (relative to the directory) matches this glob
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Skip files whose path (relative to the directory) matches this glob
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Include hidden files and directories when walking directories
+    #[arg(long)]
+    pub hidden: bool,
+
+    /// Follow symbolic links when walking directories (they are skipped by default)
+    #[arg(long)]
+    pub follow_symlinks: bool,
+
+    /// Maximum depth to descend into directories; 1 uploads only their direct contents
+    #[arg(long, value_name = "N")]
+    pub max_depth: Option<usize>,
+
+    #[command(flatten)]
+    pub send: SendArgs,
+}
+
+// How each file is checked, prepared and sent, shared by upload and watch
+#[derive(Args, Debug)]
+pub struct SendArgs {
+    /// Only upload files whose content is one of these image types (e.g. png,jpeg)
+    #[arg(long, value_enum, value_name = "TYPE", value_delimiter = ',')]
+    pub allow_types: Vec<ImageType>,
+
+    /// Refuse to upload files whose content is not a recognized image
+    #[arg(long)]
+    pub deny_non_images: bool,
+
+    /// Upload files even when the same content was uploaded before
+    #[arg(long)]
+    pub force: bool,
+
+    /// Encrypt each file with a fresh key before uploading; the key is only printed, in the URL fragment
+    #[arg(long)]
+    pub encrypt: bool,
+
+    /// Scale images down to at most this width before uploading; the file itself is left untouched
+    #[arg(long, value_name = "PIXELS")]
+    pub max_width: Option<u32>,
+
+    /// Scale images down to at most this height before uploading
+    #[arg(long, value_name = "PIXELS")]
+    pub max_height: Option<u32>,
+
+    /// Quality from 1 to 100 when re-encoding to JPEG or AVIF
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub quality: Option<u8>,
+
+    /// Re-encode images to this format before uploading
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub format: Option<TargetFormat>,
+
+    /// Upload files with their metadata untouched; by default EXIF, XMP and text metadata,
+    /// including GPS positions and camera serial numbers, are stripped from JPEG, PNG, WebP and
+    /// TIFF, and HEIC and AVIF files are refused
+    #[arg(long, conflicts_with = "keep_tags")]
+    pub keep_metadata: bool,
+
+    /// Metadata to keep while the rest is stripped (e.g. orientation,copyright) [default: orientation]
+    #[arg(long, value_enum, value_name = "TAGS", value_delimiter = ',')]
+    pub keep_tags: Vec<MetadataTag>,
+
+    /// How to report upload progress on stderr [default: bar on a terminal, none otherwise]
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub progress: Option<ProgressMode>,
+
+    /// Maximum number of attempts per file, including the first one
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_attempts: Option<u32>,
+
+    /// Delay in milliseconds before the first retry; it doubles on each further retry
+    #[arg(long, value_name = "MS")]
+    pub retry_delay: Option<u64>,
+
+    /// Random spread applied to retry delays, as a fraction between 0 and 1
+    #[arg(long, value_name = "FRACTION", value_parser = retry::parse_jitter)]
+    pub retry_jitter: Option<f64>,
+
+    /// Response statuses to retry, replacing the configured list (e.g. 429,502,503)
+    #[arg(long, value_name = "STATUS", value_delimiter = ',')]
+    pub retry_on: Vec<u16>,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct WatchArgs {
+    /// Directory to watch for new images
+    pub dir: PathBuf,
+
+    /// Also watch the subdirectories of the directory
+    #[arg(short, long)]
+    pub recursive: bool,
+
+    /// Only upload files whose path (relative to the directory) matches this glob
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Skip files whose path (relative to the directory) matches this glob
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Also upload hidden files, which are often temporary files of the program writing the image
+    #[arg(long)]
+    pub hidden: bool,
+
+    /// Milliseconds a file must go unchanged before it is uploaded
+    #[arg(long, value_name = "MS", default_value_t = 1000)]
+    pub settle: u64,
+
+    /// Move each uploaded file into this directory
+    #[arg(long, value_name = "DIR", conflicts_with = "delete")]
+    pub move_to: Option<PathBuf>,
+
+    /// Delete each file once it was uploaded
+    #[arg(long)]
+    pub delete: bool,
+
+    /// Shell command to run after each upload, with $UPLOADED_FILE, $UPLOADED_URL, $UPLOADED_ID,
+    /// $UPLOADED_DELETE_URL and $UPLOADED_OUTPUT (the printed line) set; runs before --move-to and --delete
+    #[arg(long, value_name = "COMMAND")]
+    pub exec: Option<String>,
+
+    #[command(flatten)]
+    pub send: SendArgs,
+}
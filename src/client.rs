@@ -0,0 +1,833 @@
+use crate::auth::{self, Auth, AuthConfig};
+use crate::config::Config;
+use crate::credentials;
+use crate::error::Error;
+use crate::files;
+use crate::network::{self, NetworkConfig};
+use crate::output::{self, UploadResponse};
+use crate::progress::{FileProgress, Progress};
+use crate::retry::{self, RetryPolicy};
+use crate::sniff::ImageType;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures::stream::{self, Stream, TryStreamExt};
+use reqwest::header::{HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
+use reqwest::multipart::{Form, Part};
+use reqwest::{Body, Method, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+/// Path below the endpoint that files are uploaded to, unless `upload.path` says otherwise.
+const UPLOAD_PATH: &str = "/upload";
+
+/// Name of the form field holding the file, unless `upload.field` says otherwise.
+const UPLOAD_FIELD: &str = "file";
+
+/// Path below the endpoint where uploaded images are listed, and looked up by id.
+pub const IMAGES_PATH: &str = "/images";
+
+/// Header carrying the recorded delete token when `delete` sends the default request.
+pub const DELETE_TOKEN_HEADER: &str = "X-Delete-Token";
+
+/// The `delete` section of the configuration or a profile: the request `delete` sends.
+///
+/// `path` and the header values may contain `{id}`, `{delete_token}` and `{url}`, which
+/// are filled in from the history, or from the id or URL given to `delete`.
+#[derive(Deserialize, Default, Debug, Clone)]
+pub struct DeleteConfig {
+    /// HTTP method [default: DELETE]
+    pub method: Option<String>,
+    /// Path below the endpoint, or a full URL [default: the delete URL returned by the
+    /// server, else `/images/{id}` with the delete token in an `X-Delete-Token` header
+    /// when no `headers` are set]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Credentials for the delete request, replacing `auth`
+    pub auth: Option<AuthConfig>,
+}
+
+/// The `upload` section of the configuration or a profile: the request each file is sent with.
+///
+/// `path` and the values of `query`, `headers` and `fields` may contain `{name}` (the file
+/// name sent), `{stem}`, `{extension}`, `{mime}`, `{size}` and `{sha256}` of the file.
+#[derive(Deserialize, Default, Debug, Clone)]
+pub struct UploadConfig {
+    /// HTTP method [default: POST, or PUT for the `raw` body]
+    pub method: Option<String>,
+    /// Path below the endpoint, or a full URL, which only gets the credentials when it
+    /// is below the endpoint too [default: /upload]
+    pub path: Option<String>,
+    /// Query parameters added to the URL
+    #[serde(default)]
+    pub query: BTreeMap<String, String>,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// How the file is sent [default: multipart]
+    #[serde(default)]
+    pub body: BodyMode,
+    /// Form field holding the file, or JSON field holding it for the `base64` body [default: file]
+    pub field: Option<String>,
+    /// More form fields, or JSON fields for the `base64` body, sent along with the file
+    #[serde(default)]
+    pub fields: BTreeMap<String, String>,
+}
+
+/// How the file is put into the upload request.
+#[derive(Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyMode {
+    /// A multipart/form-data form, with the file in one field
+    #[default]
+    Multipart,
+    /// The file itself as the body, with its type as the Content-Type
+    Raw,
+    /// A JSON object, with the file base64-encoded in one field
+    Base64,
+}
+
+/// The `dedup` section of the configuration or a profile: how files uploaded before are
+/// recognized by the SHA-256 of their content, so they are not uploaded again.
+#[derive(Deserialize, Default, Debug, Clone)]
+pub struct DedupConfig {
+    /// Look for earlier uploads at all [default: true]
+    pub enabled: Option<bool>,
+    /// Path below the endpoint telling whether content was uploaded, with `{hash}` for
+    /// its SHA-256, e.g. `/by-hash/{hash}` [default: only the history is checked]
+    pub lookup_path: Option<String>,
+    /// HTTP method of the lookup [default: HEAD]
+    pub lookup_method: Option<String>,
+    /// Response header holding the URL of the earlier upload [default: Location]
+    pub url_header: Option<String>,
+}
+
+/// What is uploaded for one file.
+#[derive(Clone)]
+pub struct Payload {
+    /// File name sent with the multipart part
+    pub file_name: String,
+    /// What the content was found to be, if it is a known image type
+    pub content_type: Option<ImageType>,
+    pub source: Source,
+}
+
+#[derive(Clone)]
+pub enum Source {
+    /// Streamed from disk, so files of any size can be uploaded
+    File(PathBuf),
+    /// Held in memory, such as a resized copy of the file or what was read from stdin
+    Memory(Vec<u8>),
+    /// Parts of a file with other bytes between them, such as a file stripped of its
+    /// metadata; streamed from disk like the file itself
+    Spliced(PathBuf, Vec<Splice>),
+}
+
+/// One part of a spliced file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Splice {
+    /// `length` bytes of the file, starting at `offset`
+    Range { offset: u64, length: u64 },
+    /// Bytes that are not in the file, such as the metadata that was kept
+    Data(Vec<u8>),
+}
+
+/// Size of the pieces an in-memory body is sent in, so progress can be reported.
+const MEMORY_CHUNK_SIZE: usize = 64 * 1024;
+
+impl Payload {
+    /// Uploads the file as it is on disk.
+    pub fn from_file(path: &Path, content_type: Option<ImageType>) -> Self {
+        // Get the file name
+        let file_name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("unknown_file");
+        Payload {
+            file_name: file_name.to_string(),
+            content_type,
+            source: Source::File(path.to_path_buf()),
+        }
+    }
+
+    /// Uploads content that was read from stdin.
+    pub fn from_stdin(file_name: String, data: Vec<u8>) -> Self {
+        Payload { file_name, content_type: None, source: Source::Memory(data) }
+    }
+
+    /// The file the content comes from, or only its name when it is not a file; for messages.
+    pub fn path(&self) -> &Path {
+        match &self.source {
+            Source::File(path) | Source::Spliced(path, _) => path,
+            Source::Memory(_) => Path::new(&self.file_name),
+        }
+    }
+
+    /// Reads all of the content; blocks.
+    pub fn read(&self) -> Result<Cow<'_, [u8]>, Error> {
+        match &self.source {
+            Source::File(path) => Ok(Cow::Owned(std::fs::read(path).map_err(|err| Error::io(path, err))?)),
+            Source::Memory(data) => Ok(Cow::Borrowed(data)),
+            Source::Spliced(path, splices) => {
+                let read = || {
+                    let mut file = std::fs::File::open(path)?;
+                    let mut data = Vec::new();
+                    for splice in splices {
+                        match splice {
+                            Splice::Range { offset, length } => {
+                                file.seek(SeekFrom::Start(*offset))?;
+                                (&mut file).take(*length).read_to_end(&mut data)?;
+                            }
+                            Splice::Data(bytes) => data.extend_from_slice(bytes),
+                        }
+                    }
+                    Ok(data)
+                };
+                Ok(Cow::Owned(read().map_err(|err| Error::io(path, err))?))
+            }
+        }
+    }
+
+    /// Reads all of the content without blocking.
+    pub async fn load(&self) -> Result<Vec<u8>, Error> {
+        match &self.source {
+            Source::File(path) => tokio::fs::read(path).await.map_err(|err| Error::io(path, err)),
+            Source::Memory(data) => Ok(data.clone()),
+            Source::Spliced(path, splices) => {
+                let file = File::open(path).await.map_err(|err| Error::io(path, err))?;
+                spliced_stream(file, splices.clone()).try_concat().await.map_err(|err| Error::io(path, err))
+            }
+        }
+    }
+
+    /// Reads the first `length` bytes of the content, or all of it when it is shorter.
+    pub async fn head(&self, length: usize) -> Result<Vec<u8>, Error> {
+        let mut head = Vec::with_capacity(length);
+        match &self.source {
+            Source::File(path) => {
+                let file = File::open(path).await.map_err(|err| Error::io(path, err))?;
+                file.take(length as u64).read_to_end(&mut head).await.map_err(|err| Error::io(path, err))?;
+            }
+            Source::Memory(data) => head.extend_from_slice(&data[..data.len().min(length)]),
+            Source::Spliced(path, splices) => {
+                let file = File::open(path).await.map_err(|err| Error::io(path, err))?;
+                let mut chunks = pin!(spliced_stream(file, splices.clone()));
+                while head.len() < length {
+                    let Some(chunk) = chunks.try_next().await.map_err(|err| Error::io(path, err))? else {
+                        break;
+                    };
+                    head.extend_from_slice(&chunk[..chunk.len().min(length - head.len())]);
+                }
+            }
+        }
+        Ok(head)
+    }
+
+    pub async fn len(&self) -> Result<u64, Error> {
+        match &self.source {
+            Source::File(path) => Ok(tokio::fs::metadata(path).await.map_err(|err| Error::io(path, err))?.len()),
+            Source::Memory(data) => Ok(data.len() as u64),
+            Source::Spliced(_, splices) => Ok(splices
+                .iter()
+                .map(|splice| match splice {
+                    Splice::Range { length, .. } => *length,
+                    Splice::Data(data) => data.len() as u64,
+                })
+                .sum()),
+        }
+    }
+
+    /// SHA-256 of the content, in hex.
+    pub async fn sha256(&self) -> Result<String, Error> {
+        match &self.source {
+            Source::File(path) => files::sha256(path).await,
+            Source::Memory(data) => Ok(format!("{:x}", Sha256::digest(data))),
+            Source::Spliced(path, splices) => {
+                let file = File::open(path).await.map_err(|err| Error::io(path, err))?;
+                let hasher = spliced_stream(file, splices.clone())
+                    .try_fold(Sha256::new(), |mut hasher, chunk| async move {
+                        hasher.update(&chunk);
+                        Ok(hasher)
+                    })
+                    .await
+                    .map_err(|err| Error::io(path, err))?;
+                Ok(format!("{:x}", hasher.finalize()))
+            }
+        }
+    }
+
+    /// Builds a request body that counts the bytes as the request pulls them.
+    async fn body(&self, file_progress: &FileProgress) -> Result<Body, Error> {
+        let file_progress = file_progress.clone();
+        Ok(match &self.source {
+            Source::File(path) => {
+                log::debug!("Streaming file: {:?}", path);
+                let file = File::open(path).await.map_err(|err| Error::io(path, err))?;
+                Body::wrap_stream(
+                    ReaderStream::new(file).inspect_ok(move |chunk| file_progress.advance(chunk.len() as u64)),
+                )
+            }
+            Source::Memory(data) => {
+                let chunks: Vec<Result<Vec<u8>, std::io::Error>> =
+                    data.chunks(MEMORY_CHUNK_SIZE).map(|chunk| Ok(chunk.to_vec())).collect();
+                Body::wrap_stream(
+                    stream::iter(chunks).inspect_ok(move |chunk| file_progress.advance(chunk.len() as u64)),
+                )
+            }
+            Source::Spliced(path, splices) => {
+                log::debug!("Streaming parts of file: {:?}", path);
+                let file = File::open(path).await.map_err(|err| Error::io(path, err))?;
+                Body::wrap_stream(
+                    spliced_stream(file, splices.clone())
+                        .inspect_ok(move |chunk| file_progress.advance(chunk.len() as u64)),
+                )
+            }
+        })
+    }
+}
+
+/// Reads the parts of a spliced file in order, in pieces of at most `MEMORY_CHUNK_SIZE`.
+fn spliced_stream(file: File, splices: Vec<Splice>) -> impl Stream<Item = io::Result<Vec<u8>>> {
+    // The state is the file, the parts still to come and what is left of the current range
+    stream::try_unfold((file, splices.into_iter(), 0u64), |(mut file, mut splices, remaining)| async move {
+        if remaining > 0 {
+            let mut chunk = vec![0; remaining.min(MEMORY_CHUNK_SIZE as u64) as usize];
+            file.read_exact(&mut chunk).await?;
+            let remaining = remaining - chunk.len() as u64;
+            return Ok(Some((chunk, (file, splices, remaining))));
+        }
+        match splices.next() {
+            Some(Splice::Range { offset, length }) => {
+                file.seek(SeekFrom::Start(offset)).await?;
+                Ok(Some((Vec::new(), (file, splices, length))))
+            }
+            Some(Splice::Data(data)) => Ok(Some((data, (file, splices, 0)))),
+            None => Ok(None),
+        }
+    })
+}
+
+/// Talks to the hosting service; shared by every subcommand.
+pub struct Client {
+    http: reqwest::Client,
+    /// Base URL of the service, without a trailing slash
+    endpoint: String,
+    auth: Option<Auth>,
+    retry: RetryPolicy,
+}
+
+impl Client {
+    pub fn new(endpoint: &str, auth: Option<Auth>, retry: RetryPolicy, network: &NetworkConfig) -> Result<Self, Error> {
+        Ok(Client {
+            http: network::http_client(network)?,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            auth,
+            retry,
+        })
+    }
+
+    /// Creates a client for the configured endpoint (or `url`), resolving the credentials
+    /// of the configuration and falling back to the token stored by `login`.
+    pub fn from_config(config: &Config, url: Option<&str>, retry: RetryPolicy) -> Result<Self, Error> {
+        let endpoint = config.endpoint_url(url);
+        log::debug!("Using endpoint URL: {}", auth::redact_url(&endpoint));
+
+        let auth = match &config.auth {
+            Some(auth) => Auth::from_config(auth)?,
+            None => credentials::load_token(config.profile_key())?.map(Auth::Bearer),
+        };
+        log::debug!("Using authentication: {:?}", auth);
+
+        Client::new(&endpoint, auth, retry, &config.network)
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    pub fn auth(&self) -> Option<&Auth> {
+        self.auth.as_ref()
+    }
+
+    /// The full URL of a path below the endpoint.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.endpoint, path)
+    }
+
+    /// Starts a request; credentials are only sent to URLs below the endpoint.
+    pub fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        log::debug!("Sending {} request to URL: {}", method, auth::redact_url(url));
+        let request = self.http.request(method, url);
+        match &self.auth {
+            Some(auth) if self.is_own_url(url) => auth.apply(request),
+            _ => request,
+        }
+    }
+
+    /// Whether `url` is below the endpoint, the only place credentials are sent to.
+    pub fn is_own_url(&self, url: &str) -> bool {
+        url.strip_prefix(&self.endpoint)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(['/', '?', '#']))
+    }
+
+    /// Sends the request made by `build`, retrying transient failures, and turns an
+    /// unsuccessful status into an error. `what` describes the request in warnings.
+    pub async fn send(&self, what: &str, build: impl Fn() -> RequestBuilder) -> Result<Response, Error> {
+        let response = self.send_with_retries(what, None, || async { Ok(build().send().await) }).await??;
+        check_status(response).await
+    }
+
+    /// Makes attempts with `attempt` until one gets a response that is not worth
+    /// retrying, or the policy runs out of attempts. Warnings about retries are printed
+    /// around the bars of `progress`, when there are any.
+    async fn send_with_retries<F, Fut>(
+        &self,
+        what: &str,
+        progress: Option<&Progress>,
+        mut attempt: F,
+    ) -> Result<Result<Response, reqwest::Error>, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<Result<Response, reqwest::Error>, Error>>,
+    {
+        let retry = &self.retry;
+        let mut number = 1;
+        loop {
+            // The URL of an error is shown in the warning below, and by whoever gets the error
+            let response = attempt().await?.map_err(auth::redact_error);
+
+            // Decide whether this attempt is worth repeating
+            let failure = match &response {
+                Ok(response) if retry.retries_status(response.status()) => {
+                    Some((response.status().to_string(), Some(response.headers())))
+                }
+                Err(err) if retry::is_transient(err) => Some((err.to_string(), None)),
+                _ => None,
+            };
+            let Some((reason, headers)) = failure.filter(|_| number < retry.max_attempts) else {
+                return Ok(response);
+            };
+
+            let delay = retry.delay(number, headers);
+            let warn = || {
+                log::warn!(
+                    "Attempt {}/{} to {} failed: {}; retrying in {:?}",
+                    number, retry.max_attempts, what, reason, delay
+                )
+            };
+            match progress {
+                Some(progress) => progress.suspend(warn),
+                None => warn(),
+            }
+            tokio::time::sleep(delay).await;
+            number += 1;
+        }
+    }
+
+    /// Uploads a single file with the request of the `upload` section, retrying transient
+    /// failures, and returns the parsed response of the server.
+    pub async fn upload_file(
+        &self,
+        config: &UploadConfig,
+        payload: &Payload,
+        name: &str,
+        progress: &Progress,
+    ) -> Result<UploadResponse, Error> {
+        let length = payload.len().await?;
+        let request = UploadRequest::new(self, config, payload, length).await?;
+        let file_progress = progress.start_file(name, length);
+
+        let attempt = || async {
+            // The body is consumed by each attempt, so it is built again every time
+            file_progress.restart();
+            self.send_file(&request, payload, &file_progress, length).await
+        };
+        let result = match self.send_with_retries(&format!("upload {}", name), Some(progress), attempt).await {
+            Ok(response) => read_upload_response(response).await,
+            Err(err) => Err(err),
+        };
+        file_progress.finish(result.is_ok());
+        result
+    }
+
+    /// Sends the file to the server in the body the request asks for.
+    async fn send_file(
+        &self,
+        request: &UploadRequest,
+        payload: &Payload,
+        file_progress: &FileProgress,
+        length: u64,
+    ) -> Result<Result<Response, reqwest::Error>, Error> {
+        // Credentials are added by `request` after the URL is logged, so they are never logged
+        let builder = request
+            .headers
+            .iter()
+            .fold(self.request(request.method.clone(), &request.url), |builder, (name, value)| {
+                builder.header(name, value)
+            })
+            .query(&request.query);
+
+        let mime = payload.content_type.map_or("application/octet-stream", ImageType::mime);
+        let builder = match request.body {
+            BodyMode::Multipart => {
+                // Create a multipart form; the known length keeps the request out of chunked encoding
+                let part = Part::stream_with_length(payload.body(file_progress).await?, length)
+                    .file_name(payload.file_name.clone())
+                    .mime_str(mime)?;
+                let form = request
+                    .fields
+                    .iter()
+                    .fold(Form::new(), |form, (name, value)| form.text(name.clone(), value.clone()))
+                    .part(request.field.clone(), part);
+                log::debug!("Created multipart form with file part `{}`: {} ({})", request.field, payload.file_name, mime);
+                builder.multipart(form)
+            }
+            BodyMode::Raw => {
+                let builder = if request.headers.iter().any(|(name, _)| name == CONTENT_TYPE) {
+                    builder
+                } else {
+                    builder.header(CONTENT_TYPE, mime)
+                };
+                builder.header(CONTENT_LENGTH, length).body(payload.body(file_progress).await?)
+            }
+            BodyMode::Base64 => {
+                let data = payload.load().await?;
+                let mut object: serde_json::Map<String, serde_json::Value> =
+                    request.fields.iter().map(|(name, value)| (name.clone(), value.clone().into())).collect();
+                object.insert(request.field.clone(), STANDARD.encode(&data).into());
+                // The encoded body is sent in one piece, so the progress jumps to the end
+                file_progress.advance(length);
+                builder.json(&object)
+            }
+        };
+        Ok(builder.send().await)
+    }
+
+    /// Fetches what the server knows about an uploaded image.
+    pub async fn info(&self, id: &str) -> Result<UploadResponse, Error> {
+        let url = self.url(&format!("{}/{}", IMAGES_PATH, id));
+        let response = self.send(&format!("look up {}", id), || self.request(Method::GET, &url)).await?;
+        let body = response.text().await?;
+        serde_json::from_str(&body).map_err(|err| Error::Parse(format!("{} in {:?}", err, body)))
+    }
+
+    /// Lists the images uploaded to the service. The server may answer with an array,
+    /// or with an object holding the array in `images`.
+    pub async fn list(&self) -> Result<Vec<UploadResponse>, Error> {
+        let url = self.url(IMAGES_PATH);
+        let response = self.send("list images", || self.request(Method::GET, &url)).await?;
+        let body = response.text().await?;
+
+        let mut value: serde_json::Value =
+            serde_json::from_str(&body).map_err(|err| Error::Parse(format!("{} in {:?}", err, body)))?;
+        if let Some(images) = value.get_mut("images") {
+            value = images.take();
+        }
+        serde_json::from_value(value).map_err(|err| Error::Parse(format!("image list: {}", err)))
+    }
+
+    /// Sends a delete request made from the `delete` section of the configuration.
+    pub async fn delete(&self, method: Method, url: &str, headers: &[(String, String)]) -> Result<(), Error> {
+        let build = || {
+            headers
+                .iter()
+                .fold(self.request(method.clone(), url), |request, (name, value)| request.header(name, value))
+        };
+        self.send(&format!("delete {}", url), build).await?;
+        Ok(())
+    }
+
+    /// Asks the server for an earlier upload of the content with this SHA-256 and returns
+    /// its URL, or `None` when there is none or no lookup is configured. The URL is taken
+    /// from a header, from where a redirect led, or from the body like an upload response;
+    /// warnings about retries are printed around the bars of `progress`.
+    pub async fn find_by_hash(&self, config: &DedupConfig, hash: &str, progress: &Progress) -> Result<Option<String>, Error> {
+        let Some(path) = &config.lookup_path else {
+            return Ok(None);
+        };
+        let path = output::expand(path, |key| Ok::<_, Infallible>((key == "hash").then(|| hash.to_string())));
+        let Ok(path) = path;
+        let url = self.url(&path);
+        let method = config.lookup_method.as_deref().unwrap_or("HEAD").to_uppercase();
+        let method = Method::from_bytes(method.as_bytes())
+            .map_err(|_| Error::Config(format!("`dedup.lookup_method`: invalid HTTP method `{}`", method)))?;
+
+        // Only a response from another URL than the one asked for means a redirect was followed
+        let sent_url = self.request(method.clone(), &url).build()?.url().clone();
+        let attempt = || async { Ok(self.request(method.clone(), &url).send().await) };
+        let response = self.send_with_retries(&format!("look up {}", hash), Some(progress), attempt).await??;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = check_status(response).await?;
+
+        let header = config.url_header.as_deref().unwrap_or("Location");
+        if let Some(location) = response.headers().get(header).and_then(|value| value.to_str().ok()) {
+            // A relative location is resolved against the lookup URL
+            let location = response.url().join(location).map_err(|err| Error::Parse(format!("{}: {}", header, err)))?;
+            return Ok(Some(self.without_credentials(location)));
+        }
+        if *response.url() != sent_url {
+            return Ok(Some(self.without_credentials(response.url().clone())));
+        }
+        if method != Method::HEAD {
+            return Ok(Some(UploadResponse::parse(&response.text().await?)?.url));
+        }
+        Err(Error::Parse(format!("the lookup of {} found it, but gave no `{}` header", hash, header)))
+    }
+
+    /// Removes the query credentials from a URL the server pointed to, since it is printed and recorded.
+    fn without_credentials(&self, mut url: reqwest::Url) -> String {
+        if let Some(Auth::Query(name, _)) = &self.auth {
+            let pairs: Vec<(String, String)> =
+                url.query_pairs().filter(|(key, _)| key != name).map(|(key, value)| (key.into_owned(), value.into_owned())).collect();
+            url.set_query(None);
+            if !pairs.is_empty() {
+                url.query_pairs_mut().extend_pairs(pairs);
+            }
+        }
+        url.to_string()
+    }
+
+    /// Starts downloading a URL; the body is left to the caller to stream.
+    pub async fn download(&self, url: &str) -> Result<Response, Error> {
+        self.send(&format!("download {}", url), || self.request(Method::GET, url)).await
+    }
+}
+
+/// The upload request for one file, with the placeholders of the `upload` section filled in.
+struct UploadRequest {
+    method: Method,
+    url: String,
+    query: Vec<(String, String)>,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    body: BodyMode,
+    field: String,
+    fields: Vec<(String, String)>,
+}
+
+impl UploadRequest {
+    async fn new(client: &Client, config: &UploadConfig, payload: &Payload, length: u64) -> Result<Self, Error> {
+        let file_name = Path::new(&payload.file_name);
+        let mut values = vec![
+            ("name", payload.file_name.clone()),
+            ("stem", file_name.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned())),
+            ("extension", file_name.extension().map_or(String::new(), |ext| ext.to_string_lossy().into_owned())),
+            ("mime", payload.content_type.map_or("application/octet-stream", ImageType::mime).to_string()),
+            ("size", length.to_string()),
+        ];
+        // Hashing reads the whole file, so it only happens when a template asks for it
+        let templates = config.path.iter().chain(config.query.values()).chain(config.headers.values()).chain(config.fields.values());
+        if templates.into_iter().any(|template| template.contains("{sha256}")) {
+            values.push(("sha256", payload.sha256().await?));
+        }
+        let fill = |template: &str, encode: bool| {
+            let filled = output::expand(template, |key| {
+                let value = values.iter().find(|(name, _)| *name == key).map(|(_, value)| value);
+                Ok::<_, Infallible>(value.map(|value| if encode { percent_encode(value) } else { value.clone() }))
+            });
+            let Ok(filled) = filled;
+            filled
+        };
+
+        let default_method = if config.body == BodyMode::Raw { "PUT" } else { "POST" };
+        let method = config.method.as_deref().unwrap_or(default_method).to_uppercase();
+        let method = Method::from_bytes(method.as_bytes())
+            .map_err(|_| Error::Config(format!("`upload.method`: invalid HTTP method `{}`", method)))?;
+        let path = fill(config.path.as_deref().unwrap_or(UPLOAD_PATH), true);
+        let url = if path.starts_with("http://") || path.starts_with("https://") { path } else { client.url(&path) };
+        let headers = config
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                let header_name = HeaderName::try_from(name.as_str())
+                    .map_err(|err| Error::Config(format!("`upload.headers`: {:?}: {}", name, err)))?;
+                let header_value = HeaderValue::try_from(fill(value, false))
+                    .map_err(|err| Error::Config(format!("`upload.headers.{}`: {}", name, err)))?;
+                Ok((header_name, header_value))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(UploadRequest {
+            method,
+            url,
+            query: config.query.iter().map(|(name, value)| (name.clone(), fill(value, false))).collect(),
+            headers,
+            body: config.body,
+            field: config.field.clone().unwrap_or_else(|| UPLOAD_FIELD.to_string()),
+            fields: config.fields.iter().map(|(name, value)| (name.clone(), fill(value, false))).collect(),
+        })
+    }
+}
+
+/// Encodes everything but unreserved characters, so a value stays within one path segment or query value.
+pub fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Passes a successful response through, or turns its status into an error.
+async fn check_status(response: Response) -> Result<Response, Error> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await?;
+    log::error!("Request failed. Status: {}. Response: {}", status, body);
+    Err(Error::from_status(status, body))
+}
+
+/// Parses the final response of an upload, or turns an unsuccessful status into an error.
+async fn read_upload_response(response: Result<Response, reqwest::Error>) -> Result<UploadResponse, Error> {
+    let response = check_status(response?).await?;
+
+    // Store response status
+    let status = response.status();
+    let response_text = response.text().await?; // Read response text once
+
+    if response_text.trim().is_empty() {
+        return Err(Error::Parse(format!("empty response body (status {})", status)));
+    }
+    // The response holds the delete token, so it is only shown when debugging
+    log::debug!("File uploaded successfully: {}", response_text);
+    UploadResponse::parse(&response_text)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::progress::ProgressMode;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    /// Size of the files the streaming tests upload; buffering one would show in the peak memory.
+    pub(crate) const LARGE_FILE_SIZE: u64 = 256 * 1024 * 1024;
+
+    /// Set in the child process a streaming test runs alone in.
+    const ALONE_VARIABLE: &str = "ANARCHIC_TEST_ALONE";
+
+    /// Peak resident memory of this process in KiB, as reported by the kernel.
+    fn peak_rss_kib() -> u64 {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap();
+        let line = status.lines().find(|line| line.starts_with("VmHWM:")).unwrap();
+        line.split_whitespace().nth(1).unwrap().parse().unwrap()
+    }
+
+    /// Whether `test` should go on in this process. The peak memory belongs to the whole
+    /// process, which other tests share, so the test runs again on its own in a child
+    /// process; this one only checks that it passed there.
+    pub(crate) fn run_alone(test: &str) -> bool {
+        if std::env::var_os(ALONE_VARIABLE).is_some() {
+            return true;
+        }
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([test, "--exact", "--test-threads=1", "--nocapture"])
+            .env(ALONE_VARIABLE, "1")
+            .output()
+            .unwrap();
+        let report = format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+        assert!(output.status.success() && report.contains("1 passed"), "{} failed on its own:\n{}", test, report);
+        false
+    }
+
+    /// A sparse file of `LARGE_FILE_SIZE` bytes starting with `head`; it takes no disk
+    /// space but reads back as zero bytes after `head`.
+    pub(crate) fn large_file(name: &str, head: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("anarchic-{}-{}", std::process::id(), name));
+        let mut file = std::fs::File::create(&path).unwrap();
+        std::io::Write::write_all(&mut file, head).unwrap();
+        file.set_len(LARGE_FILE_SIZE).unwrap();
+        path
+    }
+
+    /// Runs `upload`, checking that the peak memory grew by far less than the file it
+    /// sends, which buffering would add.
+    pub(crate) async fn assert_streamed<T>(upload: impl Future<Output = T>) -> T {
+        let before = peak_rss_kib();
+        let result = upload.await;
+        let growth = peak_rss_kib() - before;
+        assert!(growth < LARGE_FILE_SIZE / 4 / 1024, "peak memory grew by {} KiB", growth);
+        result
+    }
+
+    /// Minimal HTTP server that discards the body of one request as it arrives, and
+    /// answers with `url`. Returns its endpoint and the number of bytes it received.
+    pub(crate) async fn discarding_server(url: &'static str) -> (String, JoinHandle<u64>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut content_length = None;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = Some(value.trim().parse::<u64>().unwrap());
+                    }
+                }
+            }
+
+            let length = content_length.expect("request should carry a Content-Length");
+            let received = tokio::io::copy(&mut (&mut reader).take(length), &mut tokio::io::sink()).await.unwrap();
+            let response = format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}", url.len(), url);
+            reader.get_mut().write_all(response.as_bytes()).await.unwrap();
+            received
+        });
+        (endpoint, server)
+    }
+
+    #[tokio::test]
+    async fn query_credentials_stay_out_of_request_errors() {
+        // Nothing listens on the port once the listener is gone
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let auth = Auth::Query("api_key".to_string(), auth::SecretString::new("SUPERSECRET".to_string()));
+        let retry = RetryPolicy { max_attempts: 1, ..RetryPolicy::default() };
+        let client = Client::new(&endpoint, Some(auth), retry, &NetworkConfig::default()).unwrap();
+        let message = client.list().await.expect_err("nothing is listening").to_string();
+
+        assert!(message.contains("api_key=redacted"), "{}", message);
+        assert!(!message.contains("SUPERSECRET"), "{}", message);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn large_files_are_streamed_without_buffering() {
+        if !run_alone("client::tests::large_files_are_streamed_without_buffering") {
+            return;
+        }
+        let path = large_file("large.bin", &[]);
+        let (endpoint, server) = discarding_server("http://localhost/large.bin").await;
+
+        let client = Client::new(&endpoint, None, RetryPolicy::default(), &NetworkConfig::default()).unwrap();
+        let progress = Progress::new(ProgressMode::None, LARGE_FILE_SIZE, 1);
+        let payload = Payload::from_file(&path, None);
+        let result = assert_streamed(client.upload_file(&UploadConfig::default(), &payload, "large.bin", &progress)).await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(result.unwrap().url, "http://localhost/large.bin");
+        assert!(server.await.unwrap() > LARGE_FILE_SIZE);
+    }
+}
@@ -0,0 +1,32 @@
+use crate::config::Config;
+
+/// Prints the defined profiles, marking the one in use.
+pub fn list_profiles(config: &Config) {
+    if config.profiles.is_empty() {
+        eprintln!("No profiles are defined in the configuration");
+        return;
+    }
+
+    let width = config.profiles.keys().map(String::len).max().unwrap_or(0);
+    for (name, profile) in &config.profiles {
+        let marker = if config.profile.as_deref() == Some(name.as_str()) { '*' } else { ' ' };
+        let mut line = format!("{} {:width$}  {}", marker, name, profile.endpoint.as_deref().unwrap_or("-"));
+        if config.default_profile.as_deref() == Some(name.as_str()) {
+            line.push_str("  (default)");
+        }
+        if let Some(description) = &profile.description {
+            line.push_str(&format!("  {}", description));
+        }
+        println!("{}", line);
+    }
+}
+
+/// Prints the configuration files that were loaded.
+pub fn list_paths(config: &Config) {
+    if config.sources.is_empty() {
+        eprintln!("No configuration file was found");
+    }
+    for path in &config.sources {
+        println!("{}", path.display());
+    }
+}
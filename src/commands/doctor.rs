@@ -0,0 +1,66 @@
+use crate::auth::Auth;
+use crate::client::Client;
+use crate::config::Config;
+use crate::credentials;
+use crate::error::Error;
+use crate::retry::RetryPolicy;
+use reqwest::Method;
+use std::process::ExitCode;
+use std::time::Instant;
+
+/// Runs a series of checks and reports each of them; the exit code is that of the first failed check.
+pub async fn doctor(config: &Config, url: Option<&str>) -> Result<ExitCode, Error> {
+    let mut first_failure: Option<Error> = None;
+    let mut report = |check: &str, result: Result<String, Error>| match result {
+        Ok(detail) => println!("ok    {}: {}", check, detail),
+        Err(err) => {
+            println!("fail  {}: {}", check, err);
+            first_failure.get_or_insert(err);
+        }
+    };
+
+    // The configuration has already been loaded, or we would not be here
+    let sources = if config.sources.is_empty() {
+        "no files, using defaults".to_string()
+    } else {
+        let paths: Vec<String> = config.sources.iter().map(|path| path.display().to_string()).collect();
+        paths.join(", ")
+    };
+    report("configuration", Ok(sources));
+    report("profile", Ok(config.profile.clone().unwrap_or_else(|| "none".to_string())));
+
+    report(
+        "credentials file",
+        credentials::check_permissions().and_then(|_| credentials::path()).map(|path| path.display().to_string()),
+    );
+
+    // Without a client there is nothing left to check
+    let client = match Client::from_config(config, url, RetryPolicy::from_config(&config.retry)) {
+        Ok(client) => client,
+        Err(err) => {
+            report("credentials", Err(err));
+            return Ok(first_failure.map_or(ExitCode::SUCCESS, |err| err.exit_code()));
+        }
+    };
+    let auth = match (client.auth(), &config.auth) {
+        (None, _) => "none".to_string(),
+        (Some(_), None) => "token stored by `login`".to_string(),
+        (Some(Auth::Bearer(_)), Some(_)) => "bearer token".to_string(),
+        (Some(Auth::Header(name, _)), Some(_)) => format!("header {}", name),
+        (Some(Auth::Basic(username, _)), Some(_)) => format!("basic, user {}", username),
+        (Some(Auth::Query(name, _)), Some(_)) => format!("query parameter {}", name),
+    };
+    report("credentials", Ok(auth));
+
+    // Any response at all means the service can be reached; a single attempt is enough
+    let started = Instant::now();
+    let connection = client
+        .request(Method::GET, client.endpoint())
+        .send()
+        .await
+        .map(|response| format!("{} answered {} in {:?}", client.endpoint(), response.status(), started.elapsed()))
+        .map_err(Error::from);
+    report("connection", connection);
+
+    Ok(first_failure.map_or(ExitCode::SUCCESS, |err| err.exit_code()))
+}
@@ -0,0 +1,156 @@
+use crate::cli::{HistoryCommand, HistoryFilterArgs};
+use crate::error::Error;
+use crate::history::{self, Entry, ExportFormat, Filter};
+use chrono::Local;
+use globset::Glob;
+use std::io::{self, StdoutLock, Write};
+use std::process::ExitCode;
+
+/// Lists, searches, shows or exports the recorded uploads.
+pub fn history(command: &HistoryCommand) -> Result<ExitCode, Error> {
+    match command {
+        HistoryCommand::List { filter } => print_entries(&build_filter(filter, &[])?),
+        HistoryCommand::Search { words, filter } => print_entries(&build_filter(filter, words)?),
+        HistoryCommand::Show { entry } => show(entry),
+        HistoryCommand::Export { format, filter } => export(*format, &build_filter(filter, &[])?),
+    }
+}
+
+fn build_filter(args: &HistoryFilterArgs, words: &[String]) -> Result<Filter, Error> {
+    let name = match &args.name {
+        Some(pattern) => Some(
+            Glob::new(pattern)
+                .map_err(|err| Error::Usage(format!("invalid --name pattern: {}", err)))?
+                .compile_matcher(),
+        ),
+        None => None,
+    };
+    Ok(Filter {
+        since: args.since.as_deref().map(|since| history::parse_time(since, false)).transpose()?,
+        until: args.until.as_deref().map(|until| history::parse_time(until, true)).transpose()?,
+        profile: args.from_profile.clone(),
+        name,
+        words: words.to_vec(),
+    })
+}
+
+/// Prints one line per matching entry: number, local time, file name and URL.
+fn print_entries(filter: &Filter) -> Result<ExitCode, Error> {
+    let entries: Vec<(usize, Entry)> = history::load()?
+        .into_iter()
+        .filter(|(_, entry)| filter.matches(entry))
+        .collect();
+    if entries.is_empty() {
+        eprintln!("No uploads found");
+    }
+
+    let width = entries.last().map_or(1, |(number, _)| number.to_string().len());
+    write_stdout(|out| {
+        for (number, entry) in &entries {
+            writeln!(
+                out,
+                "{:>width$}  {}  {}  {}{}",
+                number,
+                entry.uploaded_at.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+                entry.file_name().unwrap_or_else(|| "-".to_string()),
+                entry.url,
+                if entry.deleted_at.is_some() { "  (deleted)" } else { "" }
+            )?;
+        }
+        Ok(())
+    })
+}
+
+/// Prints every field of one entry, found by number or URL.
+fn show(reference: &str) -> Result<ExitCode, Error> {
+    let entries = history::load()?;
+    let found = match reference.trim_start_matches('@').parse::<usize>() {
+        Ok(number) => entries.into_iter().find(|(index, _)| *index == number),
+        Err(_) => entries.into_iter().rev().find(|(_, entry)| entry.url == reference),
+    };
+    let Some((number, entry)) = found else {
+        return Err(Error::Usage(format!("no upload `{}` in the history", reference)));
+    };
+
+    let optional = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+    println!("number:        {}", number);
+    println!("uploaded at:   {}", entry.uploaded_at.with_timezone(&Local).to_rfc3339());
+    println!("source:        {}", entry.source.as_deref().map_or("-".into(), |source| source.display().to_string()));
+    println!("size:          {}", entry.size);
+    println!("sha256:        {}", entry.sha256);
+    if let Some(content_sha256) = &entry.content_sha256 {
+        println!("sent sha256:   {}", content_sha256);
+    }
+    println!("endpoint:      {}", entry.endpoint);
+    println!("profile:       {}", optional(&entry.profile));
+    println!("url:           {}", entry.url);
+    println!("remote id:     {}", optional(&entry.remote_id));
+    println!("delete token:  {}", optional(&entry.delete_token));
+    println!("delete url:    {}", optional(&entry.delete_url));
+    if let Some(deleted_at) = entry.deleted_at {
+        println!("deleted at:    {}", deleted_at.with_timezone(&Local).to_rfc3339());
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn export(format: ExportFormat, filter: &Filter) -> Result<ExitCode, Error> {
+    let entries: Vec<Entry> = history::load()?
+        .into_iter()
+        .map(|(_, entry)| entry)
+        .filter(|entry| filter.matches(entry))
+        .collect();
+
+    write_stdout(|out| match format {
+        ExportFormat::Jsonl => {
+            for entry in &entries {
+                writeln!(out, "{}", serde_json::to_string(entry).expect("history entry serializes to JSON"))?;
+            }
+            Ok(())
+        }
+        ExportFormat::Json => {
+            writeln!(out, "{}", serde_json::to_string_pretty(&entries).expect("history entries serialize to JSON"))
+        }
+        ExportFormat::Csv => {
+            writeln!(out, "uploaded_at,source,sha256,size,endpoint,profile,url,remote_id,delete_token,delete_url,deleted_at,content_sha256")?;
+            for entry in &entries {
+                let fields = [
+                    entry.uploaded_at.to_rfc3339(),
+                    entry.source.as_deref().map(|source| source.display().to_string()).unwrap_or_default(),
+                    entry.sha256.clone(),
+                    entry.size.to_string(),
+                    entry.endpoint.clone(),
+                    entry.profile.clone().unwrap_or_default(),
+                    entry.url.clone(),
+                    entry.remote_id.clone().unwrap_or_default(),
+                    entry.delete_token.clone().unwrap_or_default(),
+                    entry.delete_url.clone().unwrap_or_default(),
+                    entry.deleted_at.map(|deleted_at| deleted_at.to_rfc3339()).unwrap_or_default(),
+                    entry.content_sha256.clone().unwrap_or_default(),
+                ];
+                let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+                writeln!(out, "{}", fields.join(","))?;
+            }
+            Ok(())
+        }
+    })
+}
+
+/// Writes to stdout, which is often piped into other tools; a reader that stops early,
+/// like `head`, ends the output cleanly instead of with a panic.
+fn write_stdout(write: impl FnOnce(&mut StdoutLock) -> io::Result<()>) -> Result<ExitCode, Error> {
+    let mut out = io::stdout().lock();
+    match write(&mut out).and_then(|()| out.flush()) {
+        Ok(()) => Ok(ExitCode::SUCCESS),
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(ExitCode::SUCCESS),
+        Err(err) => Err(Error::io("stdout", err)),
+    }
+}
+
+/// Quotes a CSV field when it contains a separator, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
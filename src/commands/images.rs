@@ -0,0 +1,241 @@
+use crate::cli::OutputArgs;
+use crate::auth::Auth;
+use crate::client::{self, Client};
+use crate::config::Config;
+use crate::crypto;
+use crate::error::Error;
+use crate::history;
+use crate::output::{self, OutputFormat, UploadResponse};
+use crate::retry::RetryPolicy;
+use chrono::Local;
+use futures::StreamExt;
+use reqwest::Method;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use tokio::io::AsyncWriteExt;
+
+/// Prints what the service knows about one image.
+pub async fn info(config: &Config, url: Option<&str>, id: &str, args: &OutputArgs) -> Result<ExitCode, Error> {
+    let client = Client::from_config(config, url, RetryPolicy::from_config(&config.retry))?;
+    let response = client.info(id).await?;
+    println!("{}", format_image(&response, id, config, args, OutputFormat::Json)?);
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Prints the images uploaded to the service, one per line.
+pub async fn list(config: &Config, url: Option<&str>, args: &OutputArgs) -> Result<ExitCode, Error> {
+    let client = Client::from_config(config, url, RetryPolicy::from_config(&config.retry))?;
+    for image in client.list().await? {
+        let name = image.id.clone().unwrap_or_else(|| file_name_of(&image.url).unwrap_or_default());
+        println!("{}", format_image(&image, &name, config, args, OutputFormat::Url)?);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Deletes one image, given by id, URL or `@N` for entry N of the history, with the
+/// request from the `delete` section, and marks it as deleted in the history.
+pub async fn delete(config: &Config, url: Option<&str>, target: &str) -> Result<ExitCode, Error> {
+    let retry = RetryPolicy::from_config(&config.retry);
+    let client = match &config.delete.auth {
+        Some(auth) => Client::new(&config.endpoint_url(url), Auth::from_config(auth)?, retry, &config.network)?,
+        None => Client::from_config(config, url, retry)?,
+    };
+
+    // The history knows the id and delete token of our own uploads
+    let entries = history::load()?;
+    let found = match target.strip_prefix('@') {
+        Some(number) => {
+            let number: usize = number
+                .parse()
+                .map_err(|_| Error::Usage(format!("invalid history reference `{}`", target)))?;
+            let entry = entries.into_iter().find(|(index, _)| *index == number);
+            Some(entry.ok_or_else(|| Error::Usage(format!("no upload `{}` in the history", target)))?)
+        }
+        // Ids are only unique on one server, URLs everywhere
+        None => entries.into_iter().rev().find(|(_, entry)| {
+            entry.deleted_at.is_none()
+                && (entry.url == target || (entry.endpoint == client.endpoint() && entry.remote_id.as_deref() == Some(target)))
+        }),
+    };
+
+    let is_url = target.starts_with("http://") || target.starts_with("https://");
+    let (id, delete_token, image_url, delete_url) = match &found {
+        Some((number, entry)) => {
+            log::debug!("Found {} in the history as entry {}", target, number);
+            if let Some(deleted_at) = entry.deleted_at {
+                log::warn!("{} was already deleted on {}", target, deleted_at.with_timezone(&Local).to_rfc3339());
+            }
+            // Never send this endpoint's credentials to delete an image from another one
+            if entry.endpoint != client.endpoint() {
+                return Err(Error::Usage(format!(
+                    "{} was uploaded to {}; select its profile with --profile or --url",
+                    target, entry.endpoint
+                )));
+            }
+            (entry.remote_id.clone(), entry.delete_token.clone(), Some(entry.url.clone()), entry.delete_url.clone())
+        }
+        None if is_url => (None, None, Some(target.to_string()), None),
+        None => (Some(target.to_string()), None, None, None),
+    };
+    let values = [("id", id), ("delete_token", delete_token.clone()), ("url", image_url)];
+
+    // An explicit path wins over the delete URL the server returned
+    let (request_url, default_path) = match (&config.delete.path, delete_url) {
+        (Some(path), _) => (fill_placeholders(path, &values, true, target)?, false),
+        (None, Some(delete_url)) => (delete_url, false),
+        (None, None) => (fill_placeholders(&format!("{}/{{id}}", client::IMAGES_PATH), &values, true, target)?, true),
+    };
+    let request_url = if request_url.starts_with("http://") || request_url.starts_with("https://") {
+        request_url
+    } else {
+        client.url(&request_url)
+    };
+
+    let mut headers = config
+        .delete
+        .headers
+        .iter()
+        .map(|(name, value)| Ok((name.clone(), fill_placeholders(value, &values, false, target)?)))
+        .collect::<Result<Vec<_>, Error>>()?;
+    // The default path has no place for the delete token, so it goes in a header unless one is configured
+    if let Some(delete_token) = delete_token.filter(|_| default_path && config.delete.headers.is_empty()) {
+        headers.push((client::DELETE_TOKEN_HEADER.to_string(), delete_token));
+    }
+    let method = config.delete.method.as_deref().unwrap_or("DELETE").to_uppercase();
+    let method = Method::from_bytes(method.as_bytes())
+        .map_err(|_| Error::Config(format!("`delete.method`: invalid HTTP method `{}`", method)))?;
+
+    client.delete(method, &request_url, &headers).await?;
+    eprintln!("Deleted {}", target);
+
+    // The image is gone either way, so a history that cannot be written is only worth a warning
+    if let Some((number, _)) = found {
+        if let Err(err) = history::mark_deleted(number) {
+            log::warn!("Could not mark {} as deleted in the history: {}", target, err);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Replaces `{id}`, `{delete_token}` and `{url}`, percent-encoding the values when `encode` is set.
+fn fill_placeholders(
+    template: &str,
+    values: &[(&str, Option<String>)],
+    encode: bool,
+    target: &str,
+) -> Result<String, Error> {
+    output::expand(template, |key| {
+        let Some((name, value)) = values.iter().find(|(name, _)| *name == key) else {
+            return Ok(None);
+        };
+        let Some(value) = value else {
+            return Err(Error::Usage(format!(
+                "the delete request needs {{{}}}, which is not known for `{}`",
+                name, target
+            )));
+        };
+        Ok(Some(if encode { client::percent_encode(value) } else { value.clone() }))
+    })
+}
+
+/// Saves an image, given by id or URL, to `dest`, streaming it to disk.
+pub async fn download(
+    config: &Config,
+    url: Option<&str>,
+    image: &str,
+    dest: Option<&Path>,
+    decrypt: bool,
+) -> Result<ExitCode, Error> {
+    let client = Client::from_config(config, url, RetryPolicy::from_config(&config.retry))?;
+    if decrypt {
+        return download_decrypted(&client, image, dest).await;
+    }
+
+    // An id is looked up first to learn the URL of the image
+    let image_url = if image.starts_with("http://") || image.starts_with("https://") {
+        image.to_string()
+    } else {
+        client.info(image).await?.url
+    };
+
+    let dest = match dest {
+        Some(dest) => dest.to_path_buf(),
+        None => PathBuf::from(file_name_of(&image_url).unwrap_or_else(|| image.to_string())),
+    };
+    let response = client.download(&image_url).await?;
+    let mut body = response.bytes_stream();
+
+    if dest == Path::new("-") {
+        let mut stdout = tokio::io::stdout();
+        while let Some(chunk) = body.next().await {
+            stdout.write_all(&chunk?).await.map_err(|err| Error::io("<stdout>", err))?;
+        }
+        stdout.flush().await.map_err(|err| Error::io("<stdout>", err))?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut file = tokio::fs::File::create(&dest).await.map_err(|err| Error::io(&dest, err))?;
+    let mut written = 0;
+    while let Some(chunk) = body.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                // Do not leave a truncated image behind
+                let _ = tokio::fs::remove_file(&dest).await;
+                return Err(err.into());
+            }
+        };
+        file.write_all(&chunk).await.map_err(|err| Error::io(&dest, err))?;
+        written += chunk.len();
+    }
+    file.flush().await.map_err(|err| Error::io(&dest, err))?;
+
+    eprintln!("Saved {} bytes to {}", written, dest.display());
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Downloads an upload made with `--encrypt` and restores the original image. The whole
+/// download is held in memory, since it can only be verified once it is complete.
+async fn download_decrypted(client: &Client, image: &str, dest: Option<&Path>) -> Result<ExitCode, Error> {
+    let (image_url, key) = crypto::split_key(image)?;
+    let encrypted = client.download(image_url).await?.bytes().await?;
+    let decrypted = crypto::decrypt(&key, &encrypted)?;
+    log::debug!("Decrypted {} ({:?})", decrypted.name, decrypted.content_type);
+
+    if dest == Some(Path::new("-")) {
+        let mut stdout = tokio::io::stdout();
+        stdout.write_all(&decrypted.data).await.map_err(|err| Error::io("<stdout>", err))?;
+        stdout.flush().await.map_err(|err| Error::io("<stdout>", err))?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Only the last component of the original name is used, so it cannot point elsewhere
+    let dest = match dest {
+        Some(dest) => dest.to_path_buf(),
+        None => PathBuf::from(Path::new(&decrypted.name).file_name().unwrap_or("decrypted".as_ref())),
+    };
+    tokio::fs::write(&dest, &decrypted.data).await.map_err(|err| Error::io(&dest, err))?;
+
+    eprintln!("Saved {} bytes to {}", decrypted.data.len(), dest.display());
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Formats an image with the format from the command line or configuration.
+fn format_image(
+    image: &UploadResponse,
+    name: &str,
+    config: &Config,
+    args: &OutputArgs,
+    default: OutputFormat,
+) -> Result<String, Error> {
+    let template = args.template.as_deref().or(config.template.as_deref());
+    let format = output::resolve_format(args.output.or(config.output), template, default)?;
+    Ok(output::format(image, name, format, template))
+}
+
+/// The last path segment of a URL, e.g. `abc.png` for `https://host/i/abc.png?x=1`.
+fn file_name_of(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let name = url.path_segments()?.next_back()?;
+    (!name.is_empty()).then(|| name.to_string())
+}
@@ -0,0 +1,68 @@
+use crate::client::Client;
+use crate::config::Config;
+use crate::credentials;
+use crate::error::Error;
+use crate::retry::RetryPolicy;
+use reqwest::Method;
+use std::io::IsTerminal;
+use std::process::ExitCode;
+
+/// Asks for a token, or exchanges a username and password for one, and stores it.
+pub async fn login(config: &Config, url: Option<&str>, username: Option<String>) -> Result<ExitCode, Error> {
+    let profile = config.profile_key();
+
+    let token = match username {
+        None => prompt_secret("Token: ")?,
+        Some(username) => {
+            let password = prompt_secret(&format!("Password for {}: ", username))?;
+
+            // No credentials yet, so none are sent
+            let client = Client::new(&config.endpoint_url(url), None, RetryPolicy::from_config(&config.retry), &config.network)?;
+            let login_url = client.url(config.login.path.as_deref().unwrap_or("/login"));
+            let credentials = serde_json::json!({ "username": username, "password": password });
+            let response = client
+                .send("log in", || client.request(Method::POST, &login_url).json(&credentials))
+                .await?;
+            let body = response.text().await?;
+
+            let token_field = config.login.token_field.as_deref().unwrap_or("token");
+            let response: serde_json::Value = serde_json::from_str(&body)
+                .map_err(|err| Error::Parse(format!("login response: {}", err)))?;
+            response
+                .get(token_field)
+                .and_then(serde_json::Value::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| Error::Parse(format!("login response has no `{}` field", token_field)))?
+        }
+    };
+
+    if token.is_empty() {
+        return Err(Error::Usage("no token given".to_string()));
+    }
+    let path = credentials::save_token(profile, &token)?;
+    eprintln!("Saved token for profile `{}` to {}", profile, path.display());
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Deletes the stored token of the selected profile.
+pub fn logout(config: &Config) -> Result<ExitCode, Error> {
+    let profile = config.profile_key();
+    if credentials::remove_token(profile)? {
+        eprintln!("Removed the token of profile `{}`", profile);
+    } else {
+        eprintln!("No token is stored for profile `{}`", profile);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Reads a secret without echoing it, or as one line from stdin when it is not a terminal.
+fn prompt_secret(prompt: &str) -> Result<String, Error> {
+    let secret = if std::io::stdin().is_terminal() {
+        rpassword::prompt_password(prompt).map_err(|err| Error::io("/dev/tty", err))?
+    } else {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line).map_err(|err| Error::io("<stdin>", err))?;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    };
+    Ok(secret)
+}
@@ -0,0 +1,17 @@
+//! One function per subcommand; each returns the exit code of the run.
+
+mod config;
+mod doctor;
+mod history;
+mod images;
+mod login;
+mod upload;
+mod watch;
+
+pub use config::{list_paths, list_profiles};
+pub use doctor::doctor;
+pub use history::history;
+pub use images::{delete, download, info, list};
+pub use login::{login, logout};
+pub use upload::upload;
+pub use watch::watch;
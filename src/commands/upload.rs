@@ -0,0 +1,487 @@
+use crate::auth;
+use crate::cli::{SendArgs, UploadArgs};
+use crate::client::{Client, DedupConfig, Payload, Source, UploadConfig};
+use crate::config::Config;
+use crate::crypto::{self, Key};
+use crate::error::Error;
+use crate::files::{self, WalkOptions};
+use crate::history::{self, Entry};
+use crate::metadata::{self, MetadataConfig, MetadataTag};
+use crate::output::{self, OutputFormat};
+use crate::progress::{Progress, ProgressMode};
+use crate::retry::RetryPolicy;
+use crate::sniff::{self, ImageType};
+use crate::transform::{self, TransformConfig};
+use crate::output::UploadResponse;
+use clap::ValueEnum;
+use futures::stream::{self, StreamExt};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncReadExt;
+use tokio::sync::OnceCell;
+
+/// Name sent for the image read from stdin when `--filename` is not given; the
+/// extension of its content is added.
+const STDIN_FILE_NAME: &str = "stdin";
+
+/// Everything needed to check, prepare and send files, resolved once per run;
+/// shared by `upload` and `watch`.
+pub struct Uploader {
+    client: Client,
+    profile: Option<String>,
+    transform: TransformConfig,
+    metadata: MetadataConfig,
+    upload: UploadConfig,
+    dedup: DedupConfig,
+    allow_types: Vec<ImageType>,
+    deny_non_images: bool,
+    encrypt: bool,
+    /// Earlier uploads by the SHA-256 of what was sent, or `None` when every file is sent;
+    /// uploads made during the run are added
+    uploaded: Option<Mutex<HashMap<String, Entry>>>,
+    /// Uploads of this run by the SHA-256 of what was sent, so files with the same content
+    /// wait for the first of them instead of being sent in parallel
+    batch: Mutex<HashMap<String, Arc<OnceCell<UploadResponse>>>>,
+    output_format: OutputFormat,
+    template: Option<String>,
+    pub progress: ProgressMode,
+}
+
+impl Uploader {
+    /// Merges the command-line settings over the configuration and creates the client.
+    pub fn new(args: &SendArgs, url: Option<&str>, config: &Config) -> Result<Self, Error> {
+        // Command-line retry settings take precedence over the configuration file
+        let mut retry_config = config.retry.clone();
+        retry_config.max_attempts = args.max_attempts.or(retry_config.max_attempts);
+        retry_config.base_delay_ms = args.retry_delay.or(retry_config.base_delay_ms);
+        retry_config.jitter = args.retry_jitter.or(retry_config.jitter);
+        if !args.retry_on.is_empty() {
+            retry_config.retry_statuses = Some(args.retry_on.clone());
+        }
+        let retry = RetryPolicy::from_config(&retry_config);
+        log::debug!("Using retry policy: {:?}", retry);
+
+        // Command-line transform settings take precedence as well
+        let mut transform = config.transform.clone();
+        transform.max_width = args.max_width.or(transform.max_width);
+        transform.max_height = args.max_height.or(transform.max_height);
+        transform.quality = args.quality.or(transform.quality);
+        transform.format = args.format.or(transform.format);
+        log::debug!("Using transform settings: {:?}", transform);
+        let mut metadata = config.metadata.clone();
+        metadata.keep_all |= args.keep_metadata;
+        if !args.keep_tags.is_empty() {
+            metadata.keep = args.keep_tags.clone();
+        }
+        log::debug!("Using metadata settings: {:?}", metadata);
+
+        // Create one client shared by all uploads; the credentials are resolved once
+        let client = Client::from_config(config, url, retry)?;
+        let upload_url = config.upload.path.as_deref().filter(|path| path.starts_with("http://") || path.starts_with("https://"));
+        if let Some(upload_url) = upload_url.filter(|upload_url| client.auth().is_some() && !client.is_own_url(upload_url)) {
+            log::warn!(
+                "`upload.path` {} is not below the endpoint {}, so files are uploaded there without credentials",
+                auth::redact_url(upload_url),
+                auth::redact_url(client.endpoint())
+            );
+        }
+
+        let template = args.output.template.clone().or(config.template.clone());
+        let output_format = output::resolve_format(args.output.output.or(config.output), template.as_deref(), OutputFormat::Url)?;
+
+        // Earlier uploads to this endpoint by the SHA-256 of what was sent; encrypted content never repeats
+        let uploaded = (!args.force && !args.encrypt && config.dedup.enabled.unwrap_or(true))
+            .then(|| Mutex::new(earlier_uploads(client.endpoint())));
+
+        Ok(Uploader {
+            client,
+            profile: config.profile.clone(),
+            transform,
+            metadata,
+            upload: config.upload.clone(),
+            dedup: config.dedup.clone(),
+            allow_types: args.allow_types.clone(),
+            deny_non_images: args.deny_non_images,
+            encrypt: args.encrypt,
+            uploaded,
+            batch: Mutex::default(),
+            output_format,
+            template,
+            progress: ProgressMode::resolve(args.progress),
+        })
+    }
+
+    /// Looks at the content of a file before anything is sent: sets its type, and gives
+    /// names without an extension, such as the default one for stdin, the one of their content.
+    pub async fn check(&self, payload: &mut Payload) -> Result<(), Error> {
+        payload.content_type = sniff::check(payload, &self.allow_types, self.deny_non_images).await?;
+        if let Some(content_type) = payload.content_type.filter(|_| Path::new(&payload.file_name).extension().is_none()) {
+            payload.file_name = format!("{}.{}", payload.file_name, content_type.extension());
+            log::debug!("Uploading {} as {}", payload.path().display(), payload.file_name);
+        }
+        Ok(())
+    }
+
+    /// Prepares and sends a checked file. Returns the response, where the URL of encrypted
+    /// uploads carries the key, and the history entry when the file was uploaded now.
+    pub async fn upload(
+        &self,
+        original: Payload,
+        name: &str,
+        progress: &Progress,
+    ) -> Result<(UploadResponse, Option<Result<Entry, Error>>), Error> {
+        let (payload, key) = prepare(&original, &self.transform, &self.metadata, self.encrypt, progress).await?;
+        let (mut response, content_sha256) = self.send(&payload, name, progress).await?;
+
+        // Hash the file for the history while other uploads are still running; reused uploads are there already
+        let entry = match content_sha256 {
+            Some(content_sha256) => {
+                let profile = self.profile.as_deref();
+                Some(Entry::new(&original, Some(content_sha256), &response, self.client.endpoint(), profile).await)
+            }
+            None => None,
+        };
+
+        // The key is only printed, never recorded in the history or sent anywhere
+        if let Some(key) = key {
+            response.url = crypto::url_with_key(&response.url, &key);
+        }
+        Ok((response, entry))
+    }
+
+    /// Appends an upload to the history, so later files with the same content reuse it.
+    pub fn record(&self, name: &str, entry: Result<Entry, Error>, progress: &Progress) {
+        // A history that cannot be written should not fail an upload that worked
+        match entry.and_then(|entry| history::append(&entry).map(|()| entry)) {
+            Ok(entry) => {
+                if let Some(uploaded) = &self.uploaded {
+                    let mut uploaded = uploaded.lock().expect("earlier uploads are not poisoned");
+                    uploaded.insert(entry.uploaded_sha256().to_string(), entry);
+                }
+            }
+            Err(err) => progress.suspend(|| log::warn!("Could not record {} in the history: {}", name, err)),
+        }
+    }
+
+    /// The line printed for an upload.
+    pub fn format(&self, response: &UploadResponse, name: &str) -> String {
+        let file_name = Path::new(name).file_name().map_or(name.to_string(), |n| n.to_string_lossy().into_owned());
+        output::format(response, &file_name, self.output_format, self.template.as_deref())
+    }
+
+    /// Uploads a prepared file, unless the same content was uploaded before. Returns the
+    /// response, and the SHA-256 of what was sent for the history when it was uploaded now.
+    async fn send(&self, payload: &Payload, name: &str, progress: &Progress) -> Result<(UploadResponse, Option<String>), Error> {
+        let content_sha256 = payload.sha256().await?;
+        let Some(uploaded) = &self.uploaded else {
+            let response = self.client.upload_file(&self.upload, payload, name, progress).await?;
+            return Ok((response, Some(content_sha256)));
+        };
+
+        // The first file with this content is looked up and sent; the others get its response
+        let upload = {
+            let mut batch = self.batch.lock().expect("batch uploads are not poisoned");
+            batch.entry(content_sha256.clone()).or_default().clone()
+        };
+        let mut sent_now = false;
+        let response = upload
+            .get_or_try_init(|| async {
+                let recorded = uploaded.lock().expect("earlier uploads are not poisoned").get(&content_sha256).cloned();
+                let earlier = match recorded {
+                    Some(entry) => Some(UploadResponse {
+                        id: entry.remote_id,
+                        delete_token: entry.delete_token,
+                        delete_url: entry.delete_url,
+                        ..UploadResponse::from_url(entry.url)
+                    }),
+                    // A failed lookup should not keep the file from being uploaded
+                    None => match self.client.find_by_hash(&self.dedup, &content_sha256, progress).await {
+                        Ok(url) => url.map(UploadResponse::from_url),
+                        Err(err) => {
+                            progress.suspend(|| log::warn!("Could not look up {} on the server: {}", name, err));
+                            None
+                        }
+                    },
+                };
+                if let Some(response) = earlier {
+                    return Ok(response);
+                }
+                sent_now = true;
+                self.client.upload_file(&self.upload, payload, name, progress).await
+            })
+            .await?
+            .clone();
+
+        if sent_now {
+            return Ok((response, Some(content_sha256)));
+        }
+        progress.suspend(|| log::info!("{} was uploaded before, not uploading it again", name));
+        progress.correct_total(payload.len().await?, 0);
+        Ok((response, None))
+    }
+}
+
+/// Uploads the files named on the command line and prints the results.
+pub async fn upload(args: &UploadArgs, url: Option<&str>, config: Config) -> Result<ExitCode, Error> {
+    let uploader = Uploader::new(&args.send, url, &config)?;
+
+    // Resolve globs and directories into the list of files, keeping the order of the arguments
+    let walk_options = WalkOptions {
+        recursive: args.recursive,
+        include: files::build_globset(&args.include)?,
+        exclude: files::build_globset(&args.exclude)?,
+        hidden: args.hidden,
+        follow_symlinks: args.follow_symlinks,
+        max_depth: args.max_depth,
+    };
+    let file_paths = files::expand_paths(&args.file_paths, &walk_options);
+    log::debug!("Uploading {} file(s) with up to {} parallel jobs", file_paths.len(), args.jobs);
+    match args.file_paths.iter().filter(|path| *path == files::STDIN).count() {
+        0 if args.filename.is_some() => {
+            return Err(Error::Usage("--filename only applies to `-`, the image read from stdin".to_string()))
+        }
+        0 | 1 => {}
+        _ => return Err(Error::Usage("`-` can only be given once, since stdin can only be read once".to_string())),
+    }
+
+    // Stdin can only be read once, so it is read before anything else happens
+    let mut inputs = Vec::with_capacity(file_paths.len());
+    for entry in file_paths {
+        let input = match entry.path {
+            Ok(path) if path == Path::new(files::STDIN) => read_stdin(args.filename.clone()).await,
+            Ok(path) => Ok(Payload::from_file(&path, None)),
+            Err(err) => Err(err),
+        };
+        inputs.push((entry.name, input));
+    }
+
+    // Look at the content of every file first, so rejected files are known before anything is sent
+    for (name, input) in &mut inputs {
+        let Ok(payload) = input else { continue };
+        match uploader.check(payload).await {
+            Ok(()) if name == files::STDIN => *name = payload.file_name.clone(),
+            Ok(()) => {}
+            Err(err) => *input = Err(err),
+        }
+    }
+
+    // Set up progress reporting for the whole batch
+    let mut total_bytes = 0;
+    for payload in inputs.iter().filter_map(|(_, input)| input.as_ref().ok()) {
+        total_bytes += payload.len().await.unwrap_or(0);
+    }
+    let progress = Progress::new(uploader.progress, total_bytes, inputs.len());
+
+    // Upload in parallel; `buffered` yields the results in input order
+    let mut results = stream::iter(inputs)
+        .map(|(name, input)| {
+            let uploader = &uploader;
+            let progress = &progress;
+            async move {
+                let result = match input {
+                    Ok(payload) => uploader.upload(payload, &name, progress).await,
+                    Err(err) => Err(err),
+                };
+                (name, result)
+            }
+        })
+        .buffered(args.jobs as usize);
+
+    let mut succeeded = Vec::new();
+    let mut failed = Vec::new();
+    while let Some((name, result)) = results.next().await {
+        match result {
+            Ok((response, entry)) => {
+                if let Some(entry) = entry {
+                    uploader.record(&name, entry, &progress);
+                }
+
+                let line = uploader.format(&response, &name);
+
+                // Pair each file with its result so whole folders can be published by scripts
+                progress.suspend(|| {
+                    if args.recursive {
+                        println!("{}\t{}", name, line);
+                    } else {
+                        println!("{}", line);
+                    }
+                });
+                succeeded.push(name);
+            }
+            Err(err) => {
+                progress.suspend(|| eprintln!("Failed to upload {}", failure_message(&name, &err)));
+                failed.push((name, err));
+            }
+        }
+    }
+    progress.finish();
+
+    // Summarize batches so failures do not get lost in the output
+    if succeeded.len() + failed.len() > 1 {
+        eprintln!("Uploaded {} of {} files", succeeded.len(), succeeded.len() + failed.len());
+        for name in &succeeded {
+            eprintln!("  ok      {}", name);
+        }
+        for (name, err) in &failed {
+            eprintln!("  failed  {}", failure_message(name, err));
+        }
+    }
+
+    // The first failure decides the exit code, so scripts can tell what went wrong
+    Ok(failed.first().map_or(ExitCode::SUCCESS, |(_, err)| err.exit_code()))
+}
+
+/// Records of the uploads to `endpoint` that were not deleted, by the SHA-256 of what was sent.
+fn earlier_uploads(endpoint: &str) -> HashMap<String, Entry> {
+    let entries = history::load().unwrap_or_else(|err| {
+        log::warn!("Could not read the history to find files uploaded before: {}", err);
+        Vec::new()
+    });
+    entries
+        .into_iter()
+        .map(|(_, entry)| entry)
+        .filter(|entry| entry.endpoint == endpoint && entry.deleted_at.is_none())
+        .map(|entry| (entry.uploaded_sha256().to_string(), entry))
+        .collect()
+}
+
+/// Reads the image piped to stdin, to be sent as `file_name`.
+async fn read_stdin(file_name: Option<String>) -> Result<Payload, Error> {
+    if std::io::stdin().is_terminal() {
+        return Err(Error::Usage("stdin is a terminal; pipe an image into `-` instead".to_string()));
+    }
+
+    let mut data = Vec::new();
+    tokio::io::stdin().read_to_end(&mut data).await.map_err(|err| Error::io("<stdin>", err))?;
+    if data.is_empty() {
+        return Err(Error::Usage("nothing was piped to stdin".to_string()));
+    }
+    log::debug!("Read {} bytes from stdin", data.len());
+    Ok(Payload::from_stdin(file_name.unwrap_or_else(|| STDIN_FILE_NAME.to_string()), data))
+}
+
+/// Decides what to send for a file: the file itself, or a copy that was resized,
+/// re-encoded, stripped of its metadata or encrypted. Returns the key of encrypted copies.
+async fn prepare(
+    original: &Payload,
+    transform: &TransformConfig,
+    metadata: &MetadataConfig,
+    encrypt: bool,
+    progress: &Progress,
+) -> Result<(Payload, Option<Key>), Error> {
+    let payload = original.clone();
+    if !transform.is_active() && metadata.keep_all && !encrypt {
+        return Ok((payload, None));
+    }
+    let planned = payload.len().await?;
+    let content_type = payload.content_type;
+
+    // Decoding and encoding images takes a while, so it runs off the async threads
+    let (mut payload, result) = tokio::task::spawn_blocking({
+        let transform = transform.clone();
+        let metadata = metadata.clone();
+        move || {
+            // Re-encoded images only carry the metadata that is kept, so only what is sent as it is needs stripping
+            let keep = if metadata.keep_all { MetadataTag::value_variants() } else { &metadata.keep[..] };
+            let result = match transform::apply(&payload, &transform, keep) {
+                Ok(None) => metadata::strip_payload(&payload, &metadata).map(|source| source.zip(content_type)),
+                result => result.map(|data| data.map(|(data, new_type)| (Source::Memory(data), new_type))),
+            };
+            (payload, result)
+        }
+    })
+    .await
+    .expect("image processing does not panic");
+
+    if let Some((source, new_type)) = result? {
+        if Some(new_type) != content_type {
+            payload.file_name = Path::new(&payload.file_name)
+                .with_extension(new_type.extension())
+                .to_string_lossy()
+                .into_owned();
+        }
+        payload.content_type = Some(new_type);
+        payload.source = source;
+    }
+
+    // Encryption comes last, so the recipient gets the resized and stripped image
+    let key = if encrypt {
+        let data = match payload.source {
+            Source::Memory(data) => data,
+            _ => payload.load().await?,
+        };
+        let key = Key::generate();
+        let mime = payload.content_type.map(ImageType::mime);
+        let encrypted = crypto::encrypt(&key, &payload.file_name, mime, &data);
+        payload = Payload {
+            file_name: crypto::ENCRYPTED_FILE_NAME.to_string(),
+            content_type: None,
+            source: Source::Memory(encrypted),
+        };
+        Some(key)
+    } else {
+        None
+    };
+
+    let actual = payload.len().await?;
+    if actual != planned {
+        progress.correct_total(planned, actual);
+    }
+    Ok((payload, key))
+}
+
+/// Describes a failed upload; I/O errors already name the file.
+pub fn failure_message(name: &str, err: &Error) -> String {
+    match err {
+        Error::Io { .. } => err.to_string(),
+        _ => format!("{}: {}", name, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::AuthConfig;
+    use crate::cli::{Cli, Command};
+    use crate::client::tests::{assert_streamed, discarding_server, large_file, run_alone, LARGE_FILE_SIZE};
+    use clap::Parser;
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn stripped_files_are_streamed_without_buffering() {
+        const EXIF_SIZE: u64 = 4096;
+        if !run_alone("commands::upload::tests::stripped_files_are_streamed_without_buffering") {
+            return;
+        }
+
+        // A JPEG with EXIF data to strip, before the zero bytes of the large file
+        let mut head = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        head.extend_from_slice(&(EXIF_SIZE as u16 - 2).to_be_bytes());
+        head.extend_from_slice(b"Exif\0\0");
+        head.resize(EXIF_SIZE as usize + 2, 0);
+        head.extend_from_slice(&[0xFF, 0xDA]);
+        let path = large_file("large.jpg", &head);
+        let (endpoint, server) = discarding_server("http://localhost/large.jpg").await;
+
+        // Upload the way `upload` does, with the default metadata stripping
+        let config = Config { endpoint: Some(endpoint), auth: Some(AuthConfig::None), ..Config::default() };
+        let Some(Command::Upload(args)) = Cli::parse_from(["test", "upload", "--force", path.to_str().unwrap()]).command else {
+            unreachable!("the upload subcommand was given");
+        };
+        let uploader = Uploader::new(&args.send, None, &config).unwrap();
+        let mut payload = Payload::from_file(&path, None);
+        uploader.check(&mut payload).await.unwrap();
+
+        let progress = Progress::new(ProgressMode::None, LARGE_FILE_SIZE, 1);
+        let result = assert_streamed(uploader.upload(payload, "large.jpg", &progress)).await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(result.unwrap().0.url, "http://localhost/large.jpg");
+        // The EXIF segment is left out, and the form around the file is smaller than it
+        let received = server.await.unwrap();
+        assert!(received > LARGE_FILE_SIZE - EXIF_SIZE && received < LARGE_FILE_SIZE, "received {} bytes", received);
+    }
+}
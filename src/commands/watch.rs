@@ -0,0 +1,206 @@
+use crate::cli::WatchArgs;
+use crate::client::Payload;
+use crate::commands::upload::{failure_message, Uploader};
+use crate::config::Config;
+use crate::error::Error;
+use crate::files;
+use crate::output::UploadResponse;
+use crate::progress::Progress;
+use globset::GlobSet;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Which files in the watched directory are uploaded.
+struct Filter {
+    dir: PathBuf,
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
+    hidden: bool,
+    /// Where uploaded files are moved, which may be inside the watched directory
+    move_to: Option<PathBuf>,
+}
+
+impl Filter {
+    fn wants(&self, path: &Path) -> bool {
+        if self.move_to.as_deref().is_some_and(|move_to| path.starts_with(move_to)) {
+            return false;
+        }
+        let relative = path.strip_prefix(&self.dir).unwrap_or(path);
+        let hidden = relative
+            .components()
+            .any(|component| matches!(component, Component::Normal(name) if name.to_string_lossy().starts_with('.')));
+        (self.hidden || !hidden)
+            && self.include.as_ref().is_none_or(|set| set.is_match(relative))
+            && !self.exclude.as_ref().is_some_and(|set| set.is_match(relative))
+    }
+}
+
+/// Uploads each image written to a directory once it stopped changing, until interrupted.
+pub async fn watch(args: &WatchArgs, url: Option<&str>, config: Config) -> Result<ExitCode, Error> {
+    let uploader = Uploader::new(&args.send, url, &config)?;
+
+    // Events name absolute paths, so the directories are compared in that form
+    let dir = std::fs::canonicalize(&args.dir).map_err(|err| Error::io(&args.dir, err))?;
+    if !dir.is_dir() {
+        return Err(Error::Usage(format!("{} is not a directory", args.dir.display())));
+    }
+    let move_to = match &args.move_to {
+        Some(move_to) => {
+            std::fs::create_dir_all(move_to).map_err(|err| Error::io(move_to, err))?;
+            Some(std::fs::canonicalize(move_to).map_err(|err| Error::io(move_to, err))?)
+        }
+        None => None,
+    };
+    let filter = Filter {
+        dir: dir.clone(),
+        include: files::build_globset(&args.include)?,
+        exclude: files::build_globset(&args.exclude)?,
+        hidden: args.hidden,
+        move_to,
+    };
+
+    // The watcher calls back on its own thread; the events are handled here
+    let (sender, mut events) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let _ = sender.send(event);
+    })
+    .map_err(|err| Error::io(&dir, io::Error::other(err)))?;
+    let mode = if args.recursive { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+    watcher.watch(&dir, mode).map_err(|err| Error::io(&dir, io::Error::other(err)))?;
+    log::info!("Watching {} for new images; press Ctrl-C to stop", dir.display());
+
+    // Files written to recently, with the time they last changed
+    let settle = Duration::from_millis(args.settle);
+    let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+    let mut interrupted = std::pin::pin!(tokio::signal::ctrl_c());
+    loop {
+        let next_due = pending.values().min().map(|changed| *changed + settle);
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(Ok(event)) => track(&event, &filter, &mut pending),
+                Some(Err(err)) => log::warn!("Error while watching {}: {}", dir.display(), err),
+                None => break,
+            },
+            _ = tokio::time::sleep_until(next_due.unwrap_or_else(Instant::now)), if next_due.is_some() => {}
+            _ = &mut interrupted => break,
+        }
+
+        // Upload the files that have not changed for long enough, oldest first
+        let now = Instant::now();
+        let mut settled: Vec<_> = pending
+            .iter()
+            .filter(|(_, changed)| now >= **changed + settle)
+            .map(|(path, changed)| (*changed, path.clone()))
+            .collect();
+        settled.sort();
+        for (_, path) in settled {
+            pending.remove(&path);
+            if let Err(err) = process(&uploader, &path, args).await {
+                eprintln!("Failed to upload {}", failure_message(&path.display().to_string(), &err));
+            }
+        }
+    }
+
+    log::info!("Stopped watching {}", dir.display());
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Notes the files an event wrote to, and forgets the ones it removed or renamed away.
+fn track(event: &Event, filter: &Filter, pending: &mut HashMap<PathBuf, Instant>) {
+    log::debug!("Watch event: {:?}", event);
+    if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)) {
+        return;
+    }
+    for path in &event.paths {
+        if path.is_file() && filter.wants(path) {
+            pending.insert(path.clone(), Instant::now());
+        } else {
+            pending.remove(path);
+        }
+    }
+}
+
+/// Uploads one settled file, then runs the hook and moves or deletes the original.
+async fn process(uploader: &Uploader, path: &Path, args: &WatchArgs) -> Result<(), Error> {
+    let name = path.display().to_string();
+    let mut payload = Payload::from_file(path, None);
+    uploader.check(&mut payload).await?;
+    // Editors and browsers leave other files around while saving, which are not meant to be uploaded
+    if payload.content_type.is_none() {
+        log::debug!("Skipping {}: not a recognized image", name);
+        return Ok(());
+    }
+
+    let progress = Progress::new(uploader.progress, payload.len().await?, 1);
+    let result = uploader.upload(payload, &name, &progress).await;
+    progress.finish();
+    let (response, entry) = result?;
+    if let Some(entry) = entry {
+        uploader.record(&name, entry, &progress);
+    }
+    let line = uploader.format(&response, &name);
+    println!("{}", line);
+
+    // The original is only touched once everything else worked, so a failed hook can be retried by hand
+    if let Some(command) = &args.exec {
+        if let Err(err) = run_hook(command, path, &response, &line).await {
+            log::warn!("Keeping {}: {}", name, err);
+            return Ok(());
+        }
+    }
+    if let Some(move_to) = &args.move_to {
+        move_file(path, move_to).await?;
+    } else if args.delete {
+        tokio::fs::remove_file(path).await.map_err(|err| Error::io(path, err))?;
+        log::debug!("Deleted {}", name);
+    }
+    Ok(())
+}
+
+/// Runs the `--exec` command through the shell, with the upload described in the environment.
+async fn run_hook(command: &str, path: &Path, response: &UploadResponse, line: &str) -> Result<(), String> {
+    #[cfg(unix)]
+    let mut hook = tokio::process::Command::new("sh");
+    #[cfg(unix)]
+    hook.arg("-c");
+    #[cfg(windows)]
+    let mut hook = tokio::process::Command::new("cmd");
+    #[cfg(windows)]
+    hook.arg("/C");
+
+    // Not `ANARCHIC_*`, which would be read as configuration if the hook runs this program again
+    let status = hook
+        .arg(command)
+        .env("UPLOADED_FILE", path)
+        .env("UPLOADED_URL", &response.url)
+        .env("UPLOADED_ID", response.id.as_deref().unwrap_or_default())
+        .env("UPLOADED_DELETE_URL", response.delete_url.as_deref().unwrap_or_default())
+        .env("UPLOADED_OUTPUT", line)
+        .status()
+        .await
+        .map_err(|err| format!("could not run --exec command: {}", err))?;
+    if !status.success() {
+        return Err(format!("--exec command failed ({})", status));
+    }
+    Ok(())
+}
+
+/// Moves an uploaded file into `dir`, copying it when they are on different filesystems.
+async fn move_file(path: &Path, dir: &Path) -> Result<(), Error> {
+    let target = dir.join(path.file_name().expect("watched files have a name"));
+    if tokio::fs::try_exists(&target).await.unwrap_or(false) {
+        let err = io::Error::new(io::ErrorKind::AlreadyExists, "already exists, leaving the original in place");
+        return Err(Error::io(target, err));
+    }
+    if tokio::fs::rename(path, &target).await.is_err() {
+        tokio::fs::copy(path, &target).await.map_err(|err| Error::io(&target, err))?;
+        tokio::fs::remove_file(path).await.map_err(|err| Error::io(path, err))?;
+    }
+    log::debug!("Moved {} to {}", path.display(), target.display());
+    Ok(())
+}
@@ -0,0 +1,546 @@
+use crate::auth::AuthConfig;
+use crate::client::{DedupConfig, DeleteConfig, UploadConfig};
+use crate::credentials::{self, LoginConfig};
+use crate::error::Error;
+use crate::metadata::MetadataConfig;
+use crate::network::NetworkConfig;
+use crate::output::OutputFormat;
+use crate::retry::RetryConfig;
+use crate::transform::TransformConfig;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Name of the project-local configuration file, looked up from the working directory upwards.
+pub const PROJECT_FILE_NAME: &str = "anarchic-image-hosting-cli.json5";
+
+/// Directory name used below the XDG and system configuration directories.
+pub const APP_DIR_NAME: &str = "anarchic-image-hosting-cli";
+
+/// Prefix of the environment variables that override configuration fields.
+const ENV_PREFIX: &str = "ANARCHIC_";
+
+/// Endpoint used when neither `--url` nor the configuration sets one.
+pub const DEFAULT_ENDPOINT: &str = "http://localhost:8080";
+
+/// Environment variables with the prefix that are not configuration fields.
+const RESERVED_ENV_VARS: &[&str] = &["ANARCHIC_CONFIG", "ANARCHIC_PROFILE"];
+
+#[derive(Deserialize, Default, Debug)]
+pub struct Config {
+    pub log_level: Option<String>,
+    pub endpoint: Option<String>,
+    /// Credentials sent with every request; without them the token stored by `login` is used
+    pub auth: Option<AuthConfig>,
+    /// How `login --username` obtains a token
+    #[serde(default)]
+    pub login: LoginConfig,
+    /// The request each file is sent with by `upload` and `watch`
+    #[serde(default)]
+    pub upload: UploadConfig,
+    /// The request sent by `delete`
+    #[serde(default)]
+    pub delete: DeleteConfig,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    /// Resizing and re-encoding before upload; `--max-width` and friends take precedence
+    #[serde(default)]
+    pub transform: TransformConfig,
+    /// Metadata stripping before upload; `--keep-metadata` and `--keep-tags` take precedence
+    #[serde(default)]
+    pub metadata: MetadataConfig,
+    /// Recognizing files uploaded before; `--force` skips it
+    #[serde(default)]
+    pub dedup: DedupConfig,
+    /// Proxy, TLS, timeout and connection settings; `--proxy` and friends take precedence
+    #[serde(default)]
+    pub network: NetworkConfig,
+    /// Default for `--output`
+    pub output: Option<OutputFormat>,
+    /// Default for `--template`
+    pub template: Option<String>,
+    /// Profile used when none is selected with `--profile` or `$ANARCHIC_PROFILE`
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    /// Name of the profile whose settings were applied
+    #[serde(skip)]
+    pub profile: Option<String>,
+    /// Files the configuration was merged from, lowest precedence first
+    #[serde(skip)]
+    pub sources: Vec<PathBuf>,
+}
+
+impl Config {
+    /// Base URL of the service: `url` when given, else the configured endpoint.
+    pub fn endpoint_url(&self, url: Option<&str>) -> String {
+        url.or(self.endpoint.as_deref()).unwrap_or(DEFAULT_ENDPOINT).to_string()
+    }
+
+    /// Key under which the token of the selected profile is stored.
+    pub fn profile_key(&self) -> &str {
+        self.profile.as_deref().unwrap_or(credentials::DEFAULT_PROFILE_KEY)
+    }
+}
+
+/// Settings of one hosting endpoint; when selected they replace the top-level ones.
+///
+/// Only the fields shown by `config profiles` are kept here: `load` applies the other
+/// settings by merging them over the top level, and `parse_layer` checks them against
+/// [`Config`].
+#[derive(Deserialize, Default, Debug)]
+pub struct Profile {
+    /// Shown by `config profiles`
+    pub description: Option<String>,
+    pub endpoint: Option<String>,
+}
+
+/// Loads the configuration by merging every layer that exists.
+///
+/// From lowest to highest precedence: the system file in `/etc`, the user file in
+/// `$XDG_CONFIG_HOME`, the nearest project-local file, `$ANARCHIC_CONFIG`, the
+/// `--config` file, and finally `ANARCHIC_*` environment variables. The two files
+/// named explicitly must exist; the others are skipped silently when missing.
+///
+/// The settings of the selected profile (`profile`, else `$ANARCHIC_PROFILE`, else
+/// `default_profile`) are applied over the top-level ones; environment variables
+/// still take precedence over them.
+pub fn load(explicit: Option<&Path>, profile: Option<&str>) -> Result<Config, Error> {
+    let layers = layer_paths(
+        xdg_dir("XDG_CONFIG_HOME", ".config"),
+        find_project_file(),
+        std::env::var_os("ANARCHIC_CONFIG").filter(|path| !path.is_empty()).map(PathBuf::from),
+        explicit,
+    );
+    let profile = profile
+        .map(str::to_string)
+        .or_else(|| std::env::var("ANARCHIC_PROFILE").ok().filter(|name| !name.is_empty()));
+    load_layers(layers, std::env::vars(), profile)
+}
+
+/// The configuration files to merge, lowest precedence first, and whether each must exist.
+fn layer_paths(
+    config_home: Option<PathBuf>,
+    project_file: Option<PathBuf>,
+    env_file: Option<PathBuf>,
+    explicit: Option<&Path>,
+) -> Vec<(PathBuf, bool)> {
+    let mut layers: Vec<(PathBuf, bool)> = Vec::new();
+    layers.push((Path::new("/etc").join(APP_DIR_NAME).join("config.json5"), false));
+    if let Some(config_home) = config_home {
+        layers.push((config_home.join(APP_DIR_NAME).join("config.json5"), false));
+    }
+    if let Some(project_file) = project_file {
+        layers.push((project_file, false));
+    }
+    if let Some(path) = env_file {
+        layers.push((path, true));
+    }
+    if let Some(path) = explicit {
+        layers.push((path.to_path_buf(), true));
+    }
+    layers
+}
+
+/// Merges the files that exist among `layers`, then the `ANARCHIC_*` variables among
+/// `vars`, and applies the selected profile, else the default one.
+fn load_layers(
+    layers: Vec<(PathBuf, bool)>,
+    vars: impl IntoIterator<Item = (String, String)>,
+    profile: Option<String>,
+) -> Result<Config, Error> {
+    let mut merged = Value::Object(Map::new());
+    let mut sources = Vec::new();
+    for (path, required) in layers {
+        // Missing files are simply not part of the configuration, unless named explicitly
+        let file_content = match std::fs::read_to_string(&path) {
+            Ok(file_content) => file_content,
+            Err(err) if !required && err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(Error::Config(format!("{}: {}", path.display(), err))),
+        };
+        merge(&mut merged, parse_layer(&path, &file_content)?);
+        sources.push(path);
+    }
+
+    let overrides = env_overrides(vars, &merged)?;
+    merge(&mut merged, overrides.clone());
+
+    let profile = profile.or_else(|| merged.get("default_profile").and_then(Value::as_str).map(str::to_string));
+    if let Some(name) = &profile {
+        let Some(settings) = merged.get("profiles").and_then(|profiles| profiles.get(name)) else {
+            let defined: Vec<&str> = merged
+                .get("profiles")
+                .and_then(Value::as_object)
+                .map(|profiles| profiles.keys().map(String::as_str).collect())
+                .unwrap_or_default();
+            return Err(Error::Config(format!("unknown profile `{}` (defined: {})", name, defined.join(", "))));
+        };
+        let mut settings = settings.clone();
+        if let Some(settings) = settings.as_object_mut() {
+            settings.remove("description");
+        }
+        merge(&mut merged, settings);
+        merge(&mut merged, overrides);
+    }
+
+    let mut config: Config = serde_path_to_error::deserialize(merged)
+        .map_err(|err| Error::Config(format!("`{}`: {}", err.path(), err.inner())))?;
+    config.profile = profile;
+    config.sources = sources;
+    Ok(config)
+}
+
+/// Parses one configuration file, checking it against [`Config`] so mistakes are
+/// reported with their file, line, column and key, and unknown keys are warned about.
+fn parse_layer(path: &Path, file_content: &str) -> Result<Value, Error> {
+    let describe = |err: &json5::Error, key: Option<&str>| {
+        let json5::Error::Message { msg, location } = err;
+        // Syntax errors come with a multi-line source excerpt; the location is reported separately
+        let msg = msg.lines().find_map(|line| line.trim().strip_prefix("= ")).unwrap_or(msg);
+        let mut message = path.display().to_string();
+        if let Some(location) = location {
+            message.push_str(&format!(":{}:{}", location.line, location.column));
+        }
+        message.push_str(": ");
+        if let Some(key) = key.filter(|key| *key != ".") {
+            message.push_str(&format!("`{}`: ", key));
+        }
+        message + msg
+    };
+
+    // Parse the JSON5 configuration
+    let mut deserializer = json5::Deserializer::from_str(file_content)
+        .map_err(|err| Error::Config(describe(&err, None)))?;
+
+    let mut unknown_keys = Vec::new();
+    let mut record_unknown = |key: serde_ignored::Path| unknown_keys.push(key.to_string());
+    let checked = serde_ignored::Deserializer::new(&mut deserializer, &mut record_unknown);
+    serde_path_to_error::deserialize::<_, Config>(checked)
+        .map_err(|err| Error::Config(describe(err.inner(), Some(&err.path().to_string()))))?;
+    // The settings of profiles are not part of `Profile`, they are checked below
+    unknown_keys.retain(|key| !key.starts_with("profiles."));
+
+    let layer: Value = json5::from_str(file_content).map_err(|err| Error::Config(describe(&err, None)))?;
+    let locate = |key: &str| {
+        let field = key.rsplit('.').next().unwrap_or(key);
+        match find_key_line(file_content, field) {
+            Some(line) => format!("{}:{}", path.display(), line),
+            None => path.display().to_string(),
+        }
+    };
+    for (name, settings) in layer.get("profiles").and_then(Value::as_object).into_iter().flatten() {
+        let prefix = format!("profiles.{}", name);
+        let mut settings = settings.clone();
+        if let Some(settings) = settings.as_object_mut() {
+            settings.remove("description");
+            // Valid at the top level only
+            for key in ["default_profile", "profiles"] {
+                if settings.remove(key).is_some() {
+                    unknown_keys.push(format!("{}.{}", prefix, key));
+                }
+            }
+        }
+        let mut record_unknown = |key: serde_ignored::Path| unknown_keys.push(format!("{}.{}", prefix, key));
+        let checked = serde_ignored::Deserializer::new(settings, &mut record_unknown);
+        serde_path_to_error::deserialize::<_, Config>(checked).map_err(|err| {
+            let key = format!("{}.{}", prefix, err.path());
+            let key = key.strip_suffix("..").unwrap_or(&key);
+            Error::Config(format!("{}: `{}`: {}", locate(key), key, err.inner()))
+        })?;
+    }
+
+    // The logger is not set up yet, and typos should be visible at any log level
+    for key in unknown_keys {
+        eprintln!("Warning: {}: unknown configuration key `{}`", locate(&key), key);
+    }
+
+    Ok(layer)
+}
+
+/// Finds the line on which `key` is defined, to point at unknown keys.
+fn find_key_line(file_content: &str, key: &str) -> Option<usize> {
+    let is_key_char = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+    file_content
+        .lines()
+        .position(|line| {
+            line.match_indices(key).any(|(start, _)| {
+                let before = line[..start].trim_end_matches(['"', '\'']);
+                let after = line[start + key.len()..].trim_start_matches(['"', '\'']);
+                !before.ends_with(is_key_char) && after.trim_start().starts_with(':')
+            })
+        })
+        .map(|index| index + 1)
+}
+
+/// Returns an XDG base directory: the variable if it holds an absolute path,
+/// otherwise the fallback below `$HOME`.
+pub fn xdg_dir(variable: &str, fallback: &str) -> Option<PathBuf> {
+    std::env::var_os(variable)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback)))
+}
+
+/// Directory for the credentials and history: `$XDG_DATA_HOME/anarchic-image-hosting-cli`.
+pub fn data_dir() -> Result<PathBuf, Error> {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+        .map(|data_home| data_home.join(APP_DIR_NAME))
+        .ok_or_else(|| Error::Config("cannot locate the data directory: $HOME is not set".to_string()))
+}
+
+/// Finds the nearest project-local configuration file, starting in the working directory.
+fn find_project_file() -> Option<PathBuf> {
+    let current_dir = std::env::current_dir().ok()?;
+    current_dir
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+/// Collects `ANARCHIC_*` variables into a configuration layer, checked over `base`, the
+/// files merged so far, so errors can name the variable and a variable can set a single
+/// field of a section such as `auth`.
+///
+/// `ANARCHIC_LOG_LEVEL` sets `log_level`, and a double underscore reaches into
+/// sections: `ANARCHIC_RETRY__MAX_ATTEMPTS` sets `retry.max_attempts`. Values are
+/// read as JSON5 when they parse (numbers, booleans, arrays), otherwise as strings;
+/// a value its field does not take in that form is tried again as the string it was,
+/// so `ANARCHIC_DEFAULT_PROFILE=2024` still names a profile.
+fn env_overrides(vars: impl IntoIterator<Item = (String, String)>, base: &Value) -> Result<Value, Error> {
+    // The key, the value as given and the value as read, for each variable
+    let mut variables: Vec<(String, String, Value)> = Vec::new();
+    for (name, raw) in vars {
+        if !name.starts_with(ENV_PREFIX) || RESERVED_ENV_VARS.contains(&name.as_str()) {
+            continue;
+        }
+
+        let key = name[ENV_PREFIX.len()..].to_lowercase().replace("__", ".");
+        let value = json5::from_str::<Value>(&raw)
+            .ok()
+            .filter(|value| !value.is_object())
+            .unwrap_or_else(|| Value::String(raw.clone()));
+        variables.push((key, raw, value));
+    }
+    let env_name = |key: &str| format!("{}{}", ENV_PREFIX, key.replace('.', "__").to_uppercase());
+    // Whether a variable sets the field at `path`, or something in or around it
+    let is_set = |variables: &[(String, String, Value)], path: &str| {
+        variables.iter().any(|(key, _, _)| {
+            path == "." || key == path || key.starts_with(&format!("{}.", path)) || path.starts_with(&format!("{}.", key))
+        })
+    };
+
+    loop {
+        let mut overrides = Value::Object(Map::new());
+        for (key, _, value) in &variables {
+            // Build the nested object from the innermost field outwards
+            let layer = key
+                .rsplit('.')
+                .fold(value.clone(), |value, part| Value::Object(Map::from_iter([(part.to_string(), value)])));
+            merge(&mut overrides, layer);
+        }
+        let mut merged = base.clone();
+        merge(&mut merged, overrides.clone());
+
+        let mut unknown_keys = Vec::new();
+        let mut record_unknown = |key: serde_ignored::Path| unknown_keys.push(key.to_string());
+        let checked = serde_ignored::Deserializer::new(&merged, &mut record_unknown);
+        let err = match serde_path_to_error::deserialize::<_, Config>(checked) {
+            Ok(_) => {
+                // Unknown keys of the files were reported with their file already
+                for key in unknown_keys.iter().filter(|key| is_set(&variables, key)) {
+                    eprintln!("Warning: {}: unknown configuration key `{}`", env_name(key), key);
+                }
+                return Ok(overrides);
+            }
+            Err(err) => err,
+        };
+
+        // Values below the field in error that were not read as strings get one more try as strings
+        let path = err.path().to_string();
+        let mut retried = false;
+        for (key, raw, value) in &mut variables {
+            let below = key == &path || path == "." || key.starts_with(&format!("{}.", path));
+            if below && !value.is_string() {
+                *value = Value::String(raw.clone());
+                retried = true;
+            }
+        }
+        if !retried {
+            return Err(Error::Config(if is_set(&variables, &path) {
+                format!("{}: {}", env_name(&path), err.inner())
+            } else {
+                format!("`{}`: {}", path, err.inner())
+            }));
+        }
+    }
+}
+
+/// Merges `layer` into `base`: objects are merged key by key, anything else is replaced.
+/// Tagged objects such as `auth` are replaced whole when their `type` changes; one
+/// without a `type` yet, as built from environment variables, is merged into.
+fn merge(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (Value::Object(base), Value::Object(layer))
+            if layer.get("type").is_none_or(|kind| base.get("type").is_none_or(|base_kind| base_kind == kind)) =>
+        {
+            for (key, value) in layer {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Secret;
+
+    /// Writes each file into a fresh directory and returns their paths, in the same order.
+    fn write_layers(test: &str, files: &[&str]) -> Vec<(PathBuf, bool)> {
+        let dir = std::env::temp_dir().join(format!("anarchic-config-test-{}-{}", std::process::id(), test));
+        std::fs::create_dir_all(&dir).unwrap();
+        files
+            .iter()
+            .enumerate()
+            .map(|(index, file_content)| {
+                let path = dir.join(format!("{}.json5", index));
+                std::fs::write(&path, file_content).unwrap();
+                (path, false)
+            })
+            .collect()
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn orders_the_files_from_system_to_explicit() {
+        let layers = layer_paths(
+            Some(PathBuf::from("/home/jo/.config")),
+            Some(PathBuf::from("/work/anarchic-image-hosting-cli.json5")),
+            Some(PathBuf::from("/tmp/env.json5")),
+            Some(Path::new("given.json5")),
+        );
+        assert_eq!(
+            layers,
+            [
+                (PathBuf::from("/etc/anarchic-image-hosting-cli/config.json5"), false),
+                (PathBuf::from("/home/jo/.config/anarchic-image-hosting-cli/config.json5"), false),
+                (PathBuf::from("/work/anarchic-image-hosting-cli.json5"), false),
+                (PathBuf::from("/tmp/env.json5"), true),
+                (PathBuf::from("given.json5"), true),
+            ]
+        );
+        assert_eq!(layer_paths(None, None, None, None).len(), 1);
+    }
+
+    #[test]
+    fn later_layers_and_variables_take_precedence() {
+        let mut layers = write_layers(
+            "precedence",
+            &[
+                "{ endpoint: 'http://system', output: 'json', retry: { max_attempts: 1, base_delay_ms: 10 } }",
+                "{ endpoint: 'http://user', retry: { max_attempts: 2 } }",
+                "{ endpoint: 'http://project', template: '{url}' }",
+                "{ endpoint: 'http://env-file' }",
+                "{ endpoint: 'http://explicit', retry: { max_attempts: 5 } }",
+            ],
+        );
+        layers.push((layers[0].0.with_file_name("missing.json5"), false));
+
+        let config = load_layers(layers.clone(), vars(&[]), None).unwrap();
+        assert_eq!(config.endpoint.as_deref(), Some("http://explicit"));
+        assert_eq!(config.retry.max_attempts, Some(5));
+        assert_eq!(config.retry.base_delay_ms, Some(10));
+        assert_eq!(config.template.as_deref(), Some("{url}"));
+        assert!(matches!(config.output, Some(OutputFormat::Json)));
+        assert_eq!(config.sources.len(), 5);
+
+        let env = vars(&[("ANARCHIC_ENDPOINT", "http://variable"), ("ANARCHIC_RETRY__MAX_ATTEMPTS", "7"), ("OTHER", "1")]);
+        let config = load_layers(layers.clone(), env, None).unwrap();
+        assert_eq!(config.endpoint.as_deref(), Some("http://variable"));
+        assert_eq!(config.retry.max_attempts, Some(7));
+
+        // A file named explicitly has to exist
+        let last = layers.len() - 1;
+        layers[last].1 = true;
+        assert!(load_layers(layers, vars(&[]), None).is_err());
+    }
+
+    #[test]
+    fn profiles_apply_under_the_variables() {
+        let layers = write_layers(
+            "profiles",
+            &["{ endpoint: 'http://top', default_profile: 'staging', profiles: { staging: { endpoint: 'http://staging', description: 'Staging' }, prod: { endpoint: 'http://prod' } } }"],
+        );
+        let config = load_layers(layers.clone(), vars(&[]), None).unwrap();
+        assert_eq!(config.endpoint.as_deref(), Some("http://staging"));
+        assert_eq!(config.profile.as_deref(), Some("staging"));
+        let config = load_layers(layers.clone(), vars(&[]), Some("prod".to_string())).unwrap();
+        assert_eq!(config.endpoint.as_deref(), Some("http://prod"));
+        let config = load_layers(layers.clone(), vars(&[("ANARCHIC_ENDPOINT", "http://variable")]), None).unwrap();
+        assert_eq!(config.endpoint.as_deref(), Some("http://variable"));
+        assert!(load_layers(layers, vars(&[]), Some("missing".to_string())).is_err());
+    }
+
+    #[test]
+    fn reads_variables_in_the_form_their_field_takes() {
+        let env = vars(&[
+            ("ANARCHIC_RETRY__MAX_ATTEMPTS", "4"),
+            ("ANARCHIC_RETRY__JITTER", "0.5"),
+            ("ANARCHIC_RETRY__RETRY_STATUSES", "[500, 503]"),
+            ("ANARCHIC_NETWORK__INSECURE", "true"),
+            ("ANARCHIC_TEMPLATE", "[{name}]({url})"),
+            ("ANARCHIC_AUTH__TYPE", "bearer"),
+            ("ANARCHIC_AUTH__TOKEN", "12345"),
+            ("ANARCHIC_CONFIG", "not a field.json5"),
+        ]);
+        let config = load_layers(Vec::new(), env, None).unwrap();
+        assert_eq!(config.retry.max_attempts, Some(4));
+        assert_eq!(config.retry.jitter, Some(0.5));
+        assert_eq!(config.retry.retry_statuses, Some(vec![500, 503]));
+        assert!(config.network.insecure);
+        assert_eq!(config.template.as_deref(), Some("[{name}]({url})"));
+        let Some(AuthConfig::Bearer { token: Secret::Value(token) }) = &config.auth else {
+            panic!("unexpected auth {:?}", config.auth);
+        };
+        assert_eq!(token, "12345");
+
+        // A number that names a profile stays a string
+        let base = Value::Object(Map::new());
+        let overrides = env_overrides(vars(&[("ANARCHIC_DEFAULT_PROFILE", "2024")]), &base).unwrap();
+        assert_eq!(overrides["default_profile"], Value::String("2024".to_string()));
+
+        let err = env_overrides(vars(&[("ANARCHIC_RETRY__MAX_ATTEMPTS", "many")]), &base).expect_err("a word as a number");
+        assert!(err.to_string().starts_with("configuration error: ANARCHIC_RETRY__MAX_ATTEMPTS: "), "{}", err);
+    }
+
+    #[test]
+    fn replaces_tagged_sections_whose_type_changes() {
+        let layers = write_layers("tagged", &["{ auth: { type: 'basic', username: 'jo', password: 'secret' } }"]);
+
+        // Variables without a type add to the section of the file
+        let config = load_layers(layers.clone(), vars(&[("ANARCHIC_AUTH__USERNAME", "sam")]), None).unwrap();
+        let Some(AuthConfig::Basic { username, password: Secret::Value(password) }) = &config.auth else {
+            panic!("unexpected auth {:?}", config.auth);
+        };
+        assert_eq!((username.as_str(), password.as_str()), ("sam", "secret"));
+
+        // Another type replaces it, so no field of the basic section is left over
+        let env = vars(&[("ANARCHIC_AUTH__TYPE", "header"), ("ANARCHIC_AUTH__NAME", "X-Key"), ("ANARCHIC_AUTH__VALUE", "1")]);
+        let config = load_layers(layers, env, None).unwrap();
+        let Some(AuthConfig::Header { name, value: Secret::Value(value) }) = &config.auth else {
+            panic!("unexpected auth {:?}", config.auth);
+        };
+        assert_eq!((name.as_str(), value.as_str()), ("X-Key", "1"));
+    }
+}
@@ -0,0 +1,139 @@
+use crate::auth::SecretString;
+use crate::config;
+use crate::error::Error;
+use crate::files;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// Key under which credentials are stored when no profile is selected.
+pub const DEFAULT_PROFILE_KEY: &str = "default";
+
+/// The `login` section of the configuration or a profile.
+#[derive(Deserialize, Default, Debug, Clone)]
+pub struct LoginConfig {
+    /// Path below the endpoint where `login --username` exchanges a password for a token
+    pub path: Option<String>,
+    /// Field of the JSON response that holds the token
+    pub token_field: Option<String>,
+}
+
+/// Contents of the credentials file written by `login`.
+#[derive(Serialize, Deserialize, Default)]
+struct Credentials {
+    #[serde(default)]
+    profiles: BTreeMap<String, StoredToken>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredToken {
+    token: String,
+    /// When the token was stored, in seconds since the Unix epoch
+    saved_at: u64,
+}
+
+/// Location of the credentials file: `$XDG_DATA_HOME/anarchic-image-hosting-cli/credentials.json`.
+pub fn path() -> Result<PathBuf, Error> {
+    Ok(config::data_dir()?.join("credentials.json"))
+}
+
+/// Refuses to go on if the credentials file can be read or written by anyone but its owner.
+#[cfg(unix)]
+pub fn check_permissions() -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = path()?;
+    let mode = match fs::metadata(&path) {
+        Ok(metadata) => metadata.permissions().mode(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(Error::io(path, err)),
+    };
+
+    if mode & 0o077 != 0 {
+        return Err(Error::Config(format!(
+            "{} is accessible by other users (mode {:o}); run `chmod 600 {}` or log in again",
+            path.display(),
+            mode & 0o777,
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn check_permissions() -> Result<(), Error> {
+    Ok(())
+}
+
+/// Returns the token stored for a profile, if any.
+pub fn load_token(profile: &str) -> Result<Option<SecretString>, Error> {
+    let credentials = read()?;
+    Ok(credentials.profiles.get(profile).map(|stored| SecretString::new(stored.token.clone())))
+}
+
+/// Stores the token for a profile, replacing any previous one.
+pub fn save_token(profile: &str, token: &str) -> Result<PathBuf, Error> {
+    let mut credentials = read()?;
+    let saved_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    credentials.profiles.insert(profile.to_string(), StoredToken { token: token.to_string(), saved_at });
+    write(&credentials)
+}
+
+/// Removes the token of a profile; returns whether there was one.
+/// The file is deleted once no tokens are left.
+pub fn remove_token(profile: &str) -> Result<bool, Error> {
+    let mut credentials = read()?;
+    if credentials.profiles.remove(profile).is_none() {
+        return Ok(false);
+    }
+
+    if credentials.profiles.is_empty() {
+        let path = path()?;
+        fs::remove_file(&path).map_err(|err| Error::io(path, err))?;
+    } else {
+        write(&credentials)?;
+    }
+    Ok(true)
+}
+
+fn read() -> Result<Credentials, Error> {
+    let path = path()?;
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Credentials::default()),
+        Err(err) => return Err(Error::io(path, err)),
+    };
+    serde_json::from_str(&content).map_err(|err| Error::Config(format!("{}: {}", path.display(), err)))
+}
+
+/// Writes the file with mode 0600, replacing the old one only once the new one is complete.
+fn write(credentials: &Credentials) -> Result<PathBuf, Error> {
+    let path = path()?;
+    let dir = path.parent().expect("credentials path has a parent");
+    files::create_private_dir(dir).map_err(|err| Error::io(dir, err))?;
+
+    let temp_path = path.with_extension("json.tmp");
+    let content = serde_json::to_string_pretty(credentials).expect("credentials serialize to JSON");
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&temp_path).map_err(|err| Error::io(&temp_path, err))?;
+    file.write_all(content.as_bytes())
+        .and_then(|_| file.sync_all())
+        .map_err(|err| Error::io(&temp_path, err))?;
+
+    // A leftover temporary file may have kept looser permissions
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&temp_path, fs::Permissions::from_mode(0o600)).map_err(|err| Error::io(&temp_path, err))?;
+    }
+
+    fs::rename(&temp_path, &path).map_err(|err| Error::io(&path, err))?;
+    Ok(path)
+}
@@ -0,0 +1,176 @@
+use crate::error::Error;
+use aes_gcm::aead::{Aead, OsRng, Payload};
+use aes_gcm::{AeadCore, Aes256Gcm, KeyInit, Nonce};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+/// Marks encrypted uploads; also authenticated along with the content.
+const MAGIC: &[u8] = b"AIHENC1\0";
+
+/// Size of an AES-GCM nonce.
+const NONCE_LENGTH: usize = 12;
+
+/// File name sent for encrypted uploads, so the server learns nothing from it.
+pub const ENCRYPTED_FILE_NAME: &str = "encrypted.bin";
+
+/// A fresh AES-256-GCM key for each upload.
+pub struct Key(aes_gcm::Key<Aes256Gcm>);
+
+/// What the encrypted content holds besides the image itself.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    /// Name of the original file
+    name: String,
+    /// MIME type of the original file, if it was recognized
+    content_type: Option<String>,
+}
+
+/// An image as it was before encryption.
+pub struct Decrypted {
+    pub name: String,
+    pub content_type: Option<String>,
+    pub data: Vec<u8>,
+}
+
+impl Key {
+    pub fn generate() -> Self {
+        Key(Aes256Gcm::generate_key(OsRng))
+    }
+
+    /// Encoded for URLs, with the URL-safe alphabet and no padding.
+    pub fn to_base64(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.0)
+    }
+
+    /// Accepts both base64 alphabets, with or without padding.
+    pub fn from_base64(encoded: &str) -> Option<Self> {
+        let encoded = encoded.trim_end_matches('=').replace('+', "-").replace('/', "_");
+        let bytes = URL_SAFE_NO_PAD.decode(encoded).ok()?;
+        (bytes.len() == 32).then(|| Key(*aes_gcm::Key::<Aes256Gcm>::from_slice(&bytes)))
+    }
+}
+
+/// Appends the key to a URL as its fragment, which browsers and HTTP clients never send.
+pub fn url_with_key(url: &str, key: &Key) -> String {
+    format!("{}#key={}", url, key.to_base64())
+}
+
+/// Splits `url#key=...` into the URL to download and the key.
+pub fn split_key(url: &str) -> Result<(&str, Key), Error> {
+    let missing = || Error::Usage(format!("`{}` has no #key=... to decrypt with", url));
+    let (url, fragment) = url.split_once('#').ok_or_else(missing)?;
+    let encoded = fragment.split('&').find_map(|param| param.strip_prefix("key=")).ok_or_else(missing)?;
+    let key = Key::from_base64(encoded).ok_or_else(|| Error::Usage(format!("invalid key in `#key={}`", encoded)))?;
+    Ok((url, key))
+}
+
+/// Encrypts an image with its name and type; the result starts with a marker and the nonce.
+pub fn encrypt(key: &Key, name: &str, content_type: Option<&str>, data: &[u8]) -> Vec<u8> {
+    let header = serde_json::to_vec(&Header { name: name.to_string(), content_type: content_type.map(str::to_string) })
+        .expect("encryption header serializes to JSON");
+    let mut plaintext = Vec::with_capacity(4 + header.len() + data.len());
+    plaintext.extend_from_slice(&(header.len() as u32).to_be_bytes());
+    plaintext.extend_from_slice(&header);
+    plaintext.extend_from_slice(data);
+
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = Aes256Gcm::new(&key.0)
+        .encrypt(&nonce, Payload { msg: &plaintext, aad: MAGIC })
+        .expect("AES-GCM encryption does not fail for in-memory data");
+
+    let mut encrypted = Vec::with_capacity(MAGIC.len() + NONCE_LENGTH + ciphertext.len());
+    encrypted.extend_from_slice(MAGIC);
+    encrypted.extend_from_slice(&nonce);
+    encrypted.extend_from_slice(&ciphertext);
+    encrypted
+}
+
+/// Verifies and decrypts what `encrypt` produced.
+pub fn decrypt(key: &Key, encrypted: &[u8]) -> Result<Decrypted, Error> {
+    let Some(rest) = encrypted.strip_prefix(MAGIC) else {
+        return Err(Error::Decrypt("the download is not an encrypted upload".to_string()));
+    };
+    if rest.len() < NONCE_LENGTH {
+        return Err(Error::Decrypt("the download is truncated".to_string()));
+    }
+    let (nonce, ciphertext) = rest.split_at(NONCE_LENGTH);
+    let plaintext = Aes256Gcm::new(&key.0)
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: MAGIC })
+        .map_err(|_| Error::Decrypt("wrong key, or the download was altered".to_string()))?;
+
+    // The header was authenticated with the rest, so it is only malformed if written so
+    let invalid = || Error::Decrypt("invalid header in the decrypted content".to_string());
+    let header_length = u32::from_be_bytes(plaintext.get(..4).ok_or_else(invalid)?.try_into().unwrap()) as usize;
+    let header = plaintext.get(4..4 + header_length).ok_or_else(invalid)?;
+    let header: Header = serde_json::from_slice(header).map_err(|_| invalid())?;
+    Ok(Decrypted { name: header.name, content_type: header.content_type, data: plaintext[4 + header_length..].to_vec() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::ExitCode;
+
+    const IMAGE: &[u8] = b"\x89PNG\r\n\x1a\nnot really an image";
+
+    #[test]
+    fn decrypts_what_was_encrypted() {
+        let key = Key::generate();
+        let encrypted = encrypt(&key, "cat.png", Some("image/png"), IMAGE);
+        assert!(encrypted.starts_with(MAGIC));
+        assert!(!encrypted.windows(b"cat.png".len()).any(|window| window == b"cat.png"));
+
+        let decrypted = decrypt(&key, &encrypted).unwrap();
+        assert_eq!(decrypted.name, "cat.png");
+        assert_eq!(decrypted.content_type.as_deref(), Some("image/png"));
+        assert_eq!(decrypted.data, IMAGE);
+    }
+
+    #[test]
+    fn rejects_altered_content() {
+        let key = Key::generate();
+        let encrypted = encrypt(&key, "cat.png", None, IMAGE);
+
+        // A byte of the authentication tag, the first of the encrypted header, and one of the marker
+        for index in [encrypted.len() - 1, MAGIC.len() + NONCE_LENGTH, MAGIC.len()] {
+            let mut altered = encrypted.clone();
+            altered[index] ^= 1;
+            let err = decrypt(&key, &altered).err().expect("altered content decrypted");
+            assert_eq!(err.exit_code(), ExitCode::from(9));
+        }
+        let err = decrypt(&key, &encrypted[..MAGIC.len() + 4]).err().expect("truncated content decrypted");
+        assert_eq!(err.exit_code(), ExitCode::from(9));
+        let err = decrypt(&key, b"AIHENC0\0").err().expect("unmarked content decrypted");
+        assert_eq!(err.exit_code(), ExitCode::from(9));
+    }
+
+    #[test]
+    fn rejects_another_key() {
+        let encrypted = encrypt(&Key::generate(), "cat.png", None, IMAGE);
+        let err = decrypt(&Key::generate(), &encrypted).err().expect("decrypted with another key");
+        assert_eq!(err.exit_code(), ExitCode::from(9));
+        assert_eq!(err.to_string(), "could not decrypt: wrong key, or the download was altered");
+    }
+
+    #[test]
+    fn splits_the_key_off_urls() {
+        let key = Key::generate();
+        let url = url_with_key("https://img.example.com/a1.bin", &key);
+        let (download_url, parsed) = split_key(&url).unwrap();
+        assert_eq!(download_url, "https://img.example.com/a1.bin");
+        assert_eq!(parsed.to_base64(), key.to_base64());
+
+        // Other fragment parameters, and keys in the standard alphabet with padding, are accepted
+        let standard = base64::engine::general_purpose::STANDARD.encode(key.0);
+        let url = format!("https://img.example.com/a1.bin#view=1&key={}", standard);
+        assert_eq!(split_key(&url).unwrap().1.to_base64(), key.to_base64());
+
+        for url in ["https://img.example.com/a1.bin", "https://img.example.com/a1.bin#view=1", "https://img.example.com/a1.bin#key=short"] {
+            let err = split_key(url).err().expect("key found");
+            assert_eq!(err.exit_code(), ExitCode::from(2));
+        }
+        assert!(Key::from_base64("not base64!").is_none());
+        assert!(Key::from_base64(&URL_SAFE_NO_PAD.encode([0u8; 16])).is_none());
+    }
+}
@@ -0,0 +1,118 @@
+use crate::auth;
+use reqwest::StatusCode;
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Everything that can make a run fail.
+///
+/// Each variant maps to its own process exit code, see [`Error::exit_code`].
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Invalid command-line input that clap could not catch, such as a bad glob pattern
+    #[error("{0}")]
+    Usage(String),
+
+    /// The configuration could not be read or is invalid
+    #[error("configuration error: {0}")]
+    Config(String),
+
+    /// A local file could not be found or read
+    #[error("{}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+
+    /// The request never got a response, or the response could not be received
+    #[error("network error: {}", error_chain(.0))]
+    Network(reqwest::Error),
+
+    /// Connecting, the whole request, or waiting for the next bytes took longer than allowed
+    #[error("timed out: {}", error_chain(.0))]
+    Timeout(reqwest::Error),
+
+    /// The server rejected the request (HTTP 4xx)
+    #[error("request rejected by the server ({status}): {body}")]
+    Client { status: StatusCode, body: String },
+
+    /// The server failed to handle the request (HTTP 5xx, or any other unexpected status)
+    #[error("server error ({status}): {body}")]
+    Server { status: StatusCode, body: String },
+
+    /// The server answered with something that is not a valid upload response
+    #[error("could not parse the server response: {0}")]
+    Parse(String),
+
+    /// A downloaded image could not be decrypted
+    #[error("could not decrypt: {0}")]
+    Decrypt(String),
+}
+
+impl Error {
+    /// Creates an I/O error for the given path.
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Error::Io { path: path.into(), source }
+    }
+
+    /// Classifies an unsuccessful response by its status.
+    pub fn from_status(status: StatusCode, body: String) -> Self {
+        if status.is_client_error() {
+            Error::Client { status, body }
+        } else {
+            Error::Server { status, body }
+        }
+    }
+
+    /// The process exit code for this error; also listed in `--help`.
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode::from(match self {
+            Error::Usage(_) => 2,
+            Error::Config(_) => 3,
+            Error::Io { .. } => 4,
+            Error::Network(_) => 5,
+            Error::Client { .. } => 6,
+            Error::Server { .. } => 7,
+            Error::Parse(_) => 8,
+            Error::Decrypt(_) => 9,
+            Error::Timeout(_) => 10,
+        })
+    }
+}
+
+/// Exit codes as shown at the end of `--help`.
+pub const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0  all files were uploaded
+  2  invalid command-line arguments
+  3  configuration error
+  4  a local file could not be found or read
+  5  network error, no response from the server
+  6  the server rejected the upload (HTTP 4xx)
+  7  server error (HTTP 5xx)
+  8  the server response could not be parsed
+  9  a download could not be decrypted
+  10 a request timed out
+When several uploads fail, the code of the first failure is used.";
+
+/// The URL in the message of a request error is redacted, see [`auth::redact_error`].
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        let err = auth::redact_error(err);
+        if err.is_timeout() {
+            Error::Timeout(err)
+        } else {
+            Error::Network(err)
+        }
+    }
+}
+
+/// Joins an error with its sources, since reqwest keeps the useful part
+/// (e.g. "Connection refused") in the source chain.
+pub fn error_chain(err: &dyn std::error::Error) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        message.push_str(": ");
+        message.push_str(&err.to_string());
+        source = err.source();
+    }
+    message
+}
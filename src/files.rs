@@ -0,0 +1,180 @@
+use crate::error::Error;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncReadExt;
+use walkdir::{DirEntry, WalkDir};
+
+/// The argument standing for stdin, passed through by `expand_paths` as it is.
+pub const STDIN: &str = "-";
+
+/// A file to upload together with the name it is reported under.
+pub struct FileEntry {
+    /// The argument as given, or the path relative to the walked directory
+    /// for recursive uploads.
+    pub name: String,
+    /// The file to upload, or why the argument could not be resolved.
+    pub path: Result<PathBuf, Error>,
+}
+
+/// How directories given on the command line are walked.
+pub struct WalkOptions {
+    /// Descend into directories instead of rejecting them
+    pub recursive: bool,
+    /// Only upload files whose relative path matches one of these globs
+    pub include: Option<GlobSet>,
+    /// Skip files whose relative path matches one of these globs
+    pub exclude: Option<GlobSet>,
+    /// Include files and directories whose name starts with a dot
+    pub hidden: bool,
+    /// Follow symbolic links instead of skipping them
+    pub follow_symlinks: bool,
+    /// Maximum depth below each directory; 1 means only its direct children
+    pub max_depth: Option<usize>,
+}
+
+/// Compiles a list of glob patterns, returning `None` if the list is empty.
+pub fn build_globset(patterns: &[String]) -> Result<Option<GlobSet>, Error> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern).map_err(|err| Error::Usage(err.to_string()))?);
+    }
+    builder.build().map(Some).map_err(|err| Error::Usage(err.to_string()))
+}
+
+/// Expands the command-line arguments into the list of files to upload.
+///
+/// Arguments containing glob characters are expanded (sorted, as returned by `glob`)
+/// unless a file of that exact name exists; anything else is taken as a literal path. A pattern that matches nothing is
+/// reported as an error so it shows up in the summary instead of vanishing.
+/// Directories are walked when `options.recursive` is set.
+pub fn expand_paths(patterns: &[String], options: &WalkOptions) -> Vec<FileEntry> {
+    let mut entries = Vec::new();
+
+    for pattern in patterns {
+        if pattern == STDIN {
+            entries.push(FileEntry { name: pattern.clone(), path: Ok(PathBuf::from(STDIN)) });
+            continue;
+        }
+        // Real file names may contain glob characters too, like `shot [1].png`
+        if !pattern.contains(['*', '?', '[']) || Path::new(pattern).exists() {
+            add_path(pattern.clone(), PathBuf::from(pattern), options, &mut entries);
+            continue;
+        }
+
+        match glob::glob(pattern) {
+            Ok(matches) => {
+                let before = entries.len();
+                for path in matches {
+                    match path {
+                        Ok(path) => add_path(path.display().to_string(), path, options, &mut entries),
+                        Err(err) => {
+                            let path = err.path().to_path_buf();
+                            entries.push(FileEntry { name: path.display().to_string(), path: Err(Error::io(path, err.into())) });
+                        }
+                    }
+                }
+                if entries.len() == before {
+                    let err = io::Error::new(io::ErrorKind::NotFound, "no files matched");
+                    entries.push(FileEntry { name: pattern.clone(), path: Err(Error::io(pattern, err)) });
+                }
+            }
+            Err(err) => entries.push(FileEntry {
+                name: pattern.clone(),
+                path: Err(Error::Usage(format!("{}: invalid glob pattern: {}", pattern, err))),
+            }),
+        }
+    }
+
+    entries
+}
+
+fn add_path(name: String, path: PathBuf, options: &WalkOptions, entries: &mut Vec<FileEntry>) {
+    if !path.is_dir() {
+        // Missing files are left for the upload to report
+        entries.push(FileEntry { name, path: Ok(path) });
+    } else if options.recursive {
+        walk_directory(&path, options, entries);
+    } else {
+        let err = io::Error::new(io::ErrorKind::IsADirectory, "is a directory (use --recursive to upload its contents)");
+        entries.push(FileEntry { name, path: Err(Error::io(path, err)) });
+    }
+}
+
+fn walk_directory(root: &Path, options: &WalkOptions, entries: &mut Vec<FileEntry>) {
+    let mut walker = WalkDir::new(root)
+        .follow_links(options.follow_symlinks)
+        .sort_by_file_name();
+    if let Some(max_depth) = options.max_depth {
+        walker = walker.max_depth(max_depth);
+    }
+
+    // The root itself is always walked, even when it is hidden
+    let walk = walker
+        .into_iter()
+        .filter_entry(|entry| entry.depth() == 0 || options.hidden || !is_hidden(entry));
+
+    for entry in walk {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                let path = err.path().unwrap_or(root).to_path_buf();
+                entries.push(FileEntry { name: path.display().to_string(), path: Err(Error::io(path, err.into())) });
+                continue;
+            }
+        };
+
+        // Symlinks are only reported as files when they are followed
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let relative = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        if options.include.as_ref().is_some_and(|set| !set.is_match(relative)) {
+            log::debug!("Skipping {:?}: not matched by --include", relative);
+            continue;
+        }
+        if options.exclude.as_ref().is_some_and(|set| set.is_match(relative)) {
+            log::debug!("Skipping {:?}: matched by --exclude", relative);
+            continue;
+        }
+
+        entries.push(FileEntry {
+            name: relative.display().to_string(),
+            path: Ok(entry.into_path()),
+        });
+    }
+}
+
+fn is_hidden(entry: &DirEntry) -> bool {
+    entry.file_name().to_str().is_some_and(|name| name.starts_with('.'))
+}
+
+/// Creates a directory and its parents, readable only by the owner.
+pub fn create_private_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)
+}
+
+/// Hashes a file with SHA-256 without reading it into memory, as lowercase hex.
+pub async fn sha256(path: &Path) -> Result<String, Error> {
+    let mut file = tokio::fs::File::open(path).await.map_err(|err| Error::io(path, err))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await.map_err(|err| Error::io(path, err))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
@@ -0,0 +1,213 @@
+use crate::client::{Payload, Source};
+use crate::config;
+use crate::error::Error;
+use crate::files;
+use crate::output::UploadResponse;
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+use globset::GlobMatcher;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// One successful upload, as recorded in the history file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    pub uploaded_at: DateTime<Utc>,
+    /// Absolute path of the uploaded file
+    pub source: Option<PathBuf>,
+    pub sha256: String,
+    /// SHA-256 of what was sent, when the file was resized, stripped or encrypted first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_sha256: Option<String>,
+    pub size: u64,
+    pub endpoint: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    pub url: String,
+    /// Id of the image on the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delete_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delete_url: Option<String>,
+    /// When `delete` removed the image from the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl Entry {
+    /// Describes an upload that just finished; hashes the file, or what was read from stdin.
+    pub async fn new(
+        original: &Payload,
+        content_sha256: Option<String>,
+        response: &UploadResponse,
+        endpoint: &str,
+        profile: Option<&str>,
+    ) -> Result<Self, Error> {
+        let source = match &original.source {
+            Source::File(path) | Source::Spliced(path, _) => Some(std::path::absolute(path).map_err(|err| Error::io(path, err))?),
+            Source::Memory(_) => None,
+        };
+        let sha256 = original.sha256().await?;
+        Ok(Entry {
+            uploaded_at: Utc::now(),
+            source,
+            content_sha256: content_sha256.filter(|content_sha256| *content_sha256 != sha256),
+            sha256,
+            size: original.len().await?,
+            endpoint: endpoint.to_string(),
+            profile: profile.map(str::to_string),
+            url: response.url.clone(),
+            remote_id: response.id.clone(),
+            delete_token: response.delete_token.clone(),
+            delete_url: response.delete_url.clone(),
+            deleted_at: None,
+        })
+    }
+
+    /// SHA-256 of what the server received.
+    pub fn uploaded_sha256(&self) -> &str {
+        self.content_sha256.as_deref().unwrap_or(&self.sha256)
+    }
+
+    /// The file name of the source, used by `--name` and in listings.
+    pub fn file_name(&self) -> Option<String> {
+        let name = self.source.as_deref()?.file_name()?;
+        Some(name.to_string_lossy().into_owned())
+    }
+}
+
+/// Formats of `history export`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum ExportFormat {
+    /// One JSON object per line, as stored
+    Jsonl,
+    /// A JSON array
+    Json,
+    /// Comma-separated values with a header line
+    Csv,
+}
+
+/// Which entries `history list`, `search` and `export` show.
+#[derive(Default)]
+pub struct Filter {
+    pub since: Option<DateTime<Utc>>,
+    /// Exclusive upper bound
+    pub until: Option<DateTime<Utc>>,
+    pub profile: Option<String>,
+    pub name: Option<GlobMatcher>,
+    /// Words that must all appear in the source path, URL or remote id, ignoring case
+    pub words: Vec<String>,
+}
+
+impl Filter {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        let haystack = format!(
+            "{}\n{}\n{}",
+            entry.source.as_deref().map(Path::to_string_lossy).unwrap_or_default(),
+            entry.url,
+            entry.remote_id.as_deref().unwrap_or_default()
+        )
+        .to_lowercase();
+
+        self.since.is_none_or(|since| entry.uploaded_at >= since)
+            && self.until.is_none_or(|until| entry.uploaded_at < until)
+            && self.profile.as_ref().is_none_or(|profile| entry.profile.as_ref() == Some(profile))
+            && self
+                .name
+                .as_ref()
+                .is_none_or(|name| entry.file_name().is_some_and(|file_name| name.is_match(file_name)))
+            && self.words.iter().all(|word| haystack.contains(&word.to_lowercase()))
+    }
+}
+
+/// Location of the history file: `$XDG_DATA_HOME/anarchic-image-hosting-cli/history.jsonl`.
+pub fn path() -> Result<PathBuf, Error> {
+    Ok(config::data_dir()?.join("history.jsonl"))
+}
+
+/// Appends an entry. The file holds delete tokens, so only its owner may read it.
+pub fn append(entry: &Entry) -> Result<(), Error> {
+    let path = path()?;
+    let dir = path.parent().expect("history path has a parent");
+    files::create_private_dir(dir).map_err(|err| Error::io(dir, err))?;
+
+    let mut options = OpenOptions::new();
+    options.append(true).create(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&path).map_err(|err| Error::io(&path, err))?;
+
+    // One write per line keeps concurrent runs from interleaving their entries
+    let mut line = serde_json::to_string(entry).expect("history entry serializes to JSON");
+    line.push('\n');
+    file.write_all(line.as_bytes()).map_err(|err| Error::io(&path, err))
+}
+
+/// Reads all entries, oldest first, numbered from 1 in that order.
+pub fn load() -> Result<Vec<(usize, Entry)>, Error> {
+    let path = path()?;
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(Error::io(path, err)),
+    };
+
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line)
+                .map(|entry| (index + 1, entry))
+                .map_err(|err| Error::Config(format!("{}:{}: {}", path.display(), index + 1, err)))
+        })
+        .collect()
+}
+
+/// Records that the entry with the given number was deleted from the server.
+/// The whole file is rewritten, replacing the old one only once the new one is complete.
+pub fn mark_deleted(number: usize) -> Result<(), Error> {
+    let path = path()?;
+    let content = fs::read_to_string(&path).map_err(|err| Error::io(&path, err))?;
+
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let Some(line) = lines.get_mut(number - 1) else {
+        return Err(Error::Config(format!("{}: no entry {}", path.display(), number)));
+    };
+    let mut entry: Entry = serde_json::from_str(line)
+        .map_err(|err| Error::Config(format!("{}:{}: {}", path.display(), number, err)))?;
+    entry.deleted_at = Some(Utc::now());
+    *line = serde_json::to_string(&entry).expect("history entry serializes to JSON");
+
+    let temp_path = path.with_extension("jsonl.tmp");
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&temp_path).map_err(|err| Error::io(&temp_path, err))?;
+    file.write_all((lines.join("\n") + "\n").as_bytes())
+        .and_then(|_| file.sync_all())
+        .map_err(|err| Error::io(&temp_path, err))?;
+    fs::rename(&temp_path, &path).map_err(|err| Error::io(&path, err))
+}
+
+/// Parses `--since`/`--until`: an RFC 3339 time, or a local date such as `2024-05-01`.
+/// A date stands for its start, or with `end_of_day` for the start of the next day.
+pub fn parse_time(value: &str, end_of_day: bool) -> Result<DateTime<Utc>, Error> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| Error::Usage(format!("invalid date `{}`: expected YYYY-MM-DD or an RFC 3339 time", value)))?;
+    let date = if end_of_day { date.succ_opt().unwrap_or(date) } else { date };
+    let midnight = date.and_hms_opt(0, 0, 0).expect("midnight exists");
+    Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(|| Error::Usage(format!("`{}` does not exist in the local time zone", value)))
+}
@@ -1,114 +1,104 @@
-use clap::Parser;
-use reqwest::multipart::{Form, Part};
-use serde::Deserialize;
-use std::io::{self};
-use std::path::PathBuf;
-use tokio::fs::File;
-use tokio::io::AsyncReadExt;
-use json5;
-
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Cli {
-    /// Path to the image file to upload
-    file_path: PathBuf,
-
-    /// URL of the microservice where the file will be uploaded
-    #[arg(short, long)]
-    url: Option<String>, // Change to Option<String>
-}
-
-#[derive(Deserialize)]
-struct Config {
-    log_level: Option<String>, // Change to Option<String>
-    endpoint: Option<String>,    // Make endpoint optional
-}
-
-fn load_config(file_path: &str) -> Result<Config, io::Error> {
-    let file_content = std::fs::read_to_string(file_path).expect("Failed to read config file");        
-    // Parse the JSON5 configuration
-    json5::from_str(&file_content).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Unable to parse config file"))
-}
+mod auth;
+mod cli;
+mod client;
+mod commands;
+mod config;
+mod credentials;
+mod crypto;
+mod error;
+mod files;
+mod history;
+mod metadata;
+mod network;
+mod output;
+mod progress;
+mod retry;
+mod sniff;
+mod transform;
+
+use cli::{Cli, Command, ConfigCommand};
+use error::Error;
+use std::process::ExitCode;
 
 fn init_logger(log_level: &str) {
     std::env::set_var("RUST_LOG", log_level);
     env_logger::init();
 }
 
-
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Attempt to load the configuration
-    let (log_level, endpoint) = match load_config("anarchic-image-hosting-cli.json5") {
-        Ok(config) => {
-            // Use the log level from the config or default to "info"
-            (config.log_level.unwrap_or_else(|| "info".to_string()), config.endpoint)
-        }
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(code) => code,
         Err(err) => {
-            eprintln!("Warning: Could not load config file: {}", err);
-            // Set logging level to "error" if the config file can't be loaded
-            ("error".to_string(), None)
+            eprintln!("Error: {}", err);
+            err.exit_code()
         }
-    };
+    }
+}
+
+/// Runs the CLI and returns the exit code for the batch; errors that stop
+/// the whole run are returned as `Err`.
+async fn run() -> Result<ExitCode, Error> {
+    // Parse the command-line arguments first, they may name the configuration file
+    let args = Cli::parse_args();
+
+    // Load the configuration; a broken file is an error rather than something to guess around
+    let mut config = config::load(args.config.as_deref(), args.profile.as_deref())?;
+    // Use the log level from the config or default to "warn", which keeps responses out of the output
+    let log_level = config.log_level.clone().unwrap_or_else(|| "warn".to_string());
 
     // Initialize the logger based on the determined log level
     init_logger(&log_level);
-
-    // Parse the command-line arguments
-    let args = Cli::parse();
     log::debug!("Parsed arguments: {:?}", args);
+    log::debug!("Loaded configuration from: {:?}", config.sources);
+    log::debug!("Using profile: {:?}", config.profile);
 
-    // Determine the URL to use
-    let url = args.url.clone().unwrap_or_else(|| {
-        endpoint.clone().unwrap_or_else(|| {
-            "http://localhost:8080".to_string() // Default value if both are absent
-        })
-    }) + "/upload";
-
-    log::debug!("Using endpoint URL: {}", url);
-
-    // Read the file contents
-    let mut file = File::open(&args.file_path).await?;
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).await?;
-    log::debug!("Read {} bytes from file: {:?}", buffer.len(), args.file_path);
-
-    // Get the file name
-    let file_name = args.file_path
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("unknown_file");
-    log::debug!("Using file name: {}", file_name);
-
-    // Create a multipart form
-    let form = Form::new()
-        .part("file", Part::bytes(buffer).file_name(file_name.to_owned()));
-    log::debug!("Created multipart form with file part: {}", file_name);
-
-    // Create an HTTP client
-    let client = reqwest::Client::new();
-
-    // Send the request to the microservice
-    log::debug!("Sending request to URL: {}", url);
-    let response = client
-        .post(&url)
-        .multipart(form)
-        .send()
-        .await?;
-
-    // Store response status
-    let status = response.status();
-    let response_text = response.text().await?; // Read response text once
-
-    // Check if the request was successful
-    if status.is_success() {
-        println!("{}", response_text);
-        log::info!("File uploaded successfully: {}", response_text);
-    } else {
-        eprintln!("Failed to upload the file. Status: {}", status);
-        eprintln!("Response: {}", response_text); // Use the stored response text
-        log::error!("Failed to upload file. Status: {}. Response: {}", status, response_text);
+    // Stored tokens must stay private; login and logout rewrite or remove the file anyway,
+    // and doctor reports the problem itself
+    if !matches!(args.command, Some(Command::Login { .. } | Command::Logout | Command::Doctor)) {
+        credentials::check_permissions()?;
     }
 
-    Ok(())
+    // Network flags take precedence over the configuration
+    let network = &mut config.network;
+    network.proxy = args.network.proxy.clone().or(network.proxy.take());
+    network.no_proxy = args.network.no_proxy.clone().or(network.no_proxy.take());
+    network.ca_bundle = args.network.ca_bundle.clone().or(network.ca_bundle.take());
+    network.client_cert = args.network.client_cert.clone().or(network.client_cert.take());
+    network.client_key = args.network.client_key.clone().or(network.client_key.take());
+    network.insecure |= args.network.insecure;
+    if !args.network.spki_pins.is_empty() {
+        network.spki_pins = args.network.spki_pins.clone();
+    }
+    network.connect_timeout = args.network.connect_timeout.or(network.connect_timeout);
+    network.request_timeout = args.network.request_timeout.or(network.request_timeout);
+    network.read_idle_timeout = args.network.read_idle_timeout.or(network.read_idle_timeout);
+    network.http_version = args.network.http_version.unwrap_or(network.http_version);
+    network.tcp_keepalive = args.network.tcp_keepalive.or(network.tcp_keepalive);
+    network.pool_idle_timeout = args.network.pool_idle_timeout.or(network.pool_idle_timeout);
+    network.pool_max_idle_per_host = args.network.pool_max_idle_per_host.or(network.pool_max_idle_per_host);
+
+    let url = args.url.as_deref();
+    match &args.command {
+        None => commands::upload(&args.upload, url, config).await,
+        Some(Command::Upload(upload_args)) => commands::upload(upload_args, url, config).await,
+        Some(Command::Delete { target }) => commands::delete(&config, url, target).await,
+        Some(Command::Watch(watch_args)) => commands::watch(watch_args, url, config).await,
+        Some(Command::Info { id, output }) => commands::info(&config, url, id, output).await,
+        Some(Command::List { output }) => commands::list(&config, url, output).await,
+        Some(Command::Download { image, dest, decrypt }) => {
+            commands::download(&config, url, image, dest.as_deref(), *decrypt).await
+        }
+        Some(Command::History { command }) => commands::history(command),
+        Some(Command::Config { command }) => {
+            match command {
+                ConfigCommand::Profiles => commands::list_profiles(&config),
+                ConfigCommand::Paths => commands::list_paths(&config),
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Command::Login { username }) => commands::login(&config, url, username.clone()).await,
+        Some(Command::Logout) => commands::logout(&config),
+        Some(Command::Doctor) => commands::doctor(&config, url).await,
+    }
 }
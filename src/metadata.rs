@@ -0,0 +1,691 @@
+use crate::client::{Payload, Source, Splice};
+use crate::error::Error;
+use crate::sniff::ImageType;
+use exif::experimental::Writer;
+use exif::{Context, Field, In, Reader, Tag};
+use img_parts::jpeg::{markers, Jpeg, JpegSegment};
+use img_parts::png::{Png, PngChunk};
+use img_parts::riff::{RiffChunk, RiffContent};
+use img_parts::webp::{WebP, CHUNK_EXIF, CHUNK_VP8X, CHUNK_XMP};
+use img_parts::{Bytes, ImageEXIF};
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// PNG chunks holding metadata: EXIF, text (including XMP) and the modification time.
+const PNG_METADATA_CHUNKS: [[u8; 4]; 5] = [*b"eXIf", *b"tEXt", *b"zTXt", *b"iTXt", *b"tIME"];
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// What JPEG EXIF segments start with, before the TIFF structure.
+const JPEG_EXIF_PREFIX: &[u8] = b"Exif\0\0";
+
+/// How much of a JPEG segment is read to tell what it holds: enough for `ICC_PROFILE\0`.
+const JPEG_PREFIX_LENGTH: u64 = 12;
+
+/// Flags in the first byte of a WebP `VP8X` chunk.
+const WEBP_EXIF_FLAG: u8 = 0b0000_1000;
+const WEBP_XMP_FLAG: u8 = 0b0000_0100;
+
+/// TIFF tags needed to decode the image, kept when a TIFF file is rewritten.
+const TIFF_IMAGE_TAGS: [u16; 27] = [
+    254,   // NewSubfileType
+    256,   // ImageWidth
+    257,   // ImageLength
+    258,   // BitsPerSample
+    259,   // Compression
+    262,   // PhotometricInterpretation
+    266,   // FillOrder
+    277,   // SamplesPerPixel
+    278,   // RowsPerStrip
+    282,   // XResolution
+    283,   // YResolution
+    284,   // PlanarConfiguration
+    292,   // T4Options
+    293,   // T6Options
+    296,   // ResolutionUnit
+    301,   // TransferFunction
+    317,   // Predictor
+    318,   // WhitePoint
+    319,   // PrimaryChromaticities
+    320,   // ColorMap
+    322,   // TileWidth
+    323,   // TileLength
+    338,   // ExtraSamples
+    339,   // SampleFormat
+    347,   // JPEGTables
+    530,   // YCbCrSubSampling
+    34675, // ICC profile
+];
+
+/// TIFF tags marking a raw photo, whose full image is not in the first IFD.
+const TIFF_RAW_TAGS: [u16; 2] = [
+    330,   // SubIFDs
+    50706, // DNGVersion
+];
+
+/// Metadata that can be kept while the rest is stripped.
+#[derive(clap::ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataTag {
+    /// Which way is up; without it some photos show rotated
+    Orientation,
+    Copyright,
+    Artist,
+    /// The image description
+    Description,
+    /// When the photo was taken, with its time zone
+    Date,
+    /// Camera and lens make and model, but not their serial numbers
+    Camera,
+}
+
+impl MetadataTag {
+    /// The EXIF tags covered.
+    fn tags(self) -> &'static [Tag] {
+        match self {
+            MetadataTag::Orientation => &[Tag::Orientation],
+            MetadataTag::Copyright => &[Tag::Copyright],
+            MetadataTag::Artist => &[Tag::Artist],
+            MetadataTag::Description => &[Tag::ImageDescription],
+            MetadataTag::Date => &[
+                Tag::DateTime,
+                Tag::DateTimeOriginal,
+                Tag::DateTimeDigitized,
+                Tag::OffsetTime,
+                Tag::OffsetTimeOriginal,
+                Tag::OffsetTimeDigitized,
+                Tag::SubSecTime,
+                Tag::SubSecTimeOriginal,
+                Tag::SubSecTimeDigitized,
+            ],
+            MetadataTag::Camera => &[Tag::Make, Tag::Model, Tag::LensMake, Tag::LensModel],
+        }
+    }
+}
+
+/// The `metadata` section of the configuration or a profile: what is removed from
+/// images before they are uploaded.
+#[derive(Deserialize, Debug, Clone)]
+pub struct MetadataConfig {
+    /// Upload files with their metadata untouched
+    #[serde(default)]
+    pub keep_all: bool,
+    /// Metadata kept while the rest is stripped [default: orientation]
+    #[serde(default = "default_keep")]
+    pub keep: Vec<MetadataTag>,
+}
+
+impl Default for MetadataConfig {
+    fn default() -> Self {
+        MetadataConfig { keep_all: false, keep: default_keep() }
+    }
+}
+
+/// Photos taken with the camera turned show sideways without their orientation.
+fn default_keep() -> Vec<MetadataTag> {
+    vec![MetadataTag::Orientation]
+}
+
+/// Strips the metadata of an image as configured. Returns what to send instead, or `None`
+/// when it can be uploaded as it is. A file is only read.
+///
+/// JPEG and PNG files are only scanned, and sent as the parts of the file around their
+/// metadata, so large photos are never held in memory.
+pub fn strip_payload(payload: &Payload, config: &MetadataConfig) -> Result<Option<Source>, Error> {
+    if config.keep_all {
+        return Ok(None);
+    }
+    let path = payload.path();
+    let content_type = match payload.content_type {
+        Some(content_type @ (ImageType::Jpeg | ImageType::Png | ImageType::Webp | ImageType::Tiff)) => content_type,
+        // Their metadata, GPS positions included, cannot be removed, so they are not sent unless asked to
+        Some(content_type @ (ImageType::Avif | ImageType::Heic)) => {
+            let message = format!(
+                "cannot strip metadata from {} files; use --keep-metadata to upload it as it is",
+                content_type.name()
+            );
+            return Err(Error::io(path, io::Error::new(io::ErrorKind::InvalidData, message)));
+        }
+        _ => return Ok(None),
+    };
+
+    let stripped = match &payload.source {
+        Source::File(file_path) if matches!(content_type, ImageType::Jpeg | ImageType::Png) => {
+            let file = std::fs::File::open(file_path).map_err(|err| Error::io(file_path, err))?;
+            splice(&mut BufReader::new(file), content_type, &config.keep).map(|splices| {
+                let splices = splices?;
+                log::debug!("Stripped metadata from {} into {} parts", path.display(), splices.len());
+                Some(Source::Spliced(file_path.clone(), splices))
+            })
+        }
+        _ => {
+            let data = match payload.read()? {
+                Cow::Owned(data) => Bytes::from(data),
+                Cow::Borrowed(data) => Bytes::copy_from_slice(data),
+            };
+            strip(path, data, content_type, &config.keep).map(|data| data.map(Source::Memory))
+        }
+    };
+    stripped.map_err(|err| {
+        let message = format!("cannot strip metadata ({}); use --keep-metadata to upload it as it is", err);
+        Error::io(path, io::Error::new(io::ErrorKind::InvalidData, message))
+    })
+}
+
+/// Removes EXIF, XMP, IPTC and text metadata except for the `keep` tags, without
+/// touching the compressed image data. Color profiles are kept, since they change
+/// how the image looks. Returns `None` when there was nothing to remove.
+pub fn strip(path: &Path, data: Bytes, content_type: ImageType, keep: &[MetadataTag]) -> io::Result<Option<Vec<u8>>> {
+    let invalid = |err: img_parts::Error| io::Error::new(io::ErrorKind::InvalidData, err);
+    let stripped = match content_type {
+        ImageType::Jpeg | ImageType::Png => match splice(&mut Cursor::new(&data), content_type, keep)? {
+            Some(splices) => assemble(&data, &splices),
+            None => data.to_vec(),
+        },
+        ImageType::Webp => {
+            let mut webp = WebP::from_bytes(data.clone()).map_err(invalid)?;
+            strip_webp(&mut webp, keep);
+            let mut stripped = Vec::new();
+            webp.encoder().write_to(&mut stripped)?;
+            stripped
+        }
+        ImageType::Tiff => strip_tiff(&data, keep)?,
+        other => unreachable!("{:?} has no metadata to strip", other),
+    };
+
+    if data == stripped {
+        log::debug!("{} has no metadata to strip", path.display());
+        return Ok(None);
+    }
+    log::debug!("Stripped {} bytes of metadata from {}", data.len() as i64 - stripped.len() as i64, path.display());
+    Ok(Some(stripped))
+}
+
+/// The parts of a file to send, with neighbouring ranges of the file joined.
+#[derive(Default)]
+struct Splices {
+    splices: Vec<Splice>,
+    changed: bool,
+}
+
+impl Splices {
+    fn keep(&mut self, offset: u64, length: u64) {
+        if let Some(Splice::Range { offset: start, length: kept }) = self.splices.last_mut() {
+            if *start + *kept == offset {
+                *kept += length;
+                return;
+            }
+        }
+        if length > 0 {
+            self.splices.push(Splice::Range { offset, length });
+        }
+    }
+
+    fn remove(&mut self) {
+        self.changed = true;
+    }
+
+    fn insert(&mut self, data: Vec<u8>) {
+        self.changed = true;
+        self.splices.push(Splice::Data(data));
+    }
+}
+
+/// Finds the metadata of a JPEG or PNG image by reading only the headers of its segments
+/// or chunks, and the EXIF data when tags are kept. Returns the parts of the file to send
+/// instead, or `None` when there is nothing to remove.
+fn splice<R: Read + Seek>(reader: &mut R, content_type: ImageType, keep: &[MetadataTag]) -> io::Result<Option<Vec<Splice>>> {
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let mut splices = Splices::default();
+    match content_type {
+        ImageType::Jpeg => splice_jpeg(reader, end, keep, &mut splices)?,
+        ImageType::Png => splice_png(reader, end, keep, &mut splices)?,
+        other => unreachable!("{:?} is not spliced", other),
+    }
+    Ok(splices.changed.then_some(splices.splices))
+}
+
+/// Goes through the segments up to the image data; what follows is kept as it is.
+fn splice_jpeg<R: Read + Seek>(reader: &mut R, end: u64, keep: &[MetadataTag], splices: &mut Splices) -> io::Result<()> {
+    let mut marker = [0; 2];
+    reader.read_exact(&mut marker)?;
+    if marker != [markers::P, markers::SOI] {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a JPEG image"));
+    }
+    splices.keep(0, 2);
+
+    let mut offset = 2;
+    let mut exif_seen = false;
+    while offset < end {
+        reader.read_exact(&mut marker)?;
+        match marker {
+            // Fill bytes may come before a marker
+            [markers::P, markers::P] => {
+                splices.keep(offset, 1);
+                offset += 1;
+                reader.seek(SeekFrom::Start(offset))?;
+                continue;
+            }
+            [markers::P, markers::SOS | markers::EOI] => break,
+            [markers::P, markers::RST0..=markers::RST7] => {
+                splices.keep(offset, 2);
+                offset += 2;
+                continue;
+            }
+            [markers::P, _] => {}
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "expected a JPEG marker")),
+        }
+        let mut length = [0; 2];
+        reader.read_exact(&mut length)?;
+        let length = 2 + u16::from_be_bytes(length) as u64;
+        if length < 4 || offset + length > end {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "JPEG segment beyond the end of the file"));
+        }
+
+        // The start of the contents tells EXIF, XMP and color profiles apart
+        let mut contents = Vec::new();
+        reader.by_ref().take((length - 4).min(JPEG_PREFIX_LENGTH)).read_to_end(&mut contents)?;
+        if is_jpeg_image_segment(marker[1], &contents) {
+            splices.keep(offset, length);
+        } else {
+            splices.remove();
+            if marker[1] == markers::APP1 && contents.starts_with(JPEG_EXIF_PREFIX) && !exif_seen && !keep.is_empty() {
+                exif_seen = true;
+                contents.resize((length - 4) as usize, 0);
+                reader.read_exact(&mut contents[JPEG_PREFIX_LENGTH.min(length - 4) as usize..])?;
+                let kept = kept_exif(&contents[JPEG_EXIF_PREFIX.len()..], keep)
+                    .map(|exif| [JPEG_EXIF_PREFIX, &exif].concat())
+                    .filter(|contents| contents.len() <= u16::MAX as usize - 2);
+                if let Some(kept) = kept {
+                    splices.insert(JpegSegment::new_with_contents(markers::APP1, kept.into()).encoder().bytes().to_vec());
+                }
+            }
+        }
+        offset += length;
+        reader.seek(SeekFrom::Start(offset))?;
+    }
+
+    splices.keep(offset, end - offset);
+    Ok(())
+}
+
+/// Goes through the chunks up to the end of the image; anything after it is kept as it is.
+fn splice_png<R: Read + Seek>(reader: &mut R, end: u64, keep: &[MetadataTag], splices: &mut Splices) -> io::Result<()> {
+    let mut signature = [0; 8];
+    reader.read_exact(&mut signature)?;
+    if signature != PNG_SIGNATURE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a PNG image"));
+    }
+    splices.keep(0, 8);
+
+    let mut offset = 8;
+    let mut exif_seen = false;
+    while offset < end {
+        // Length, type, contents and CRC
+        let mut header = [0; 8];
+        reader.read_exact(&mut header)?;
+        let length = 12 + u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let kind = [header[4], header[5], header[6], header[7]];
+        if offset + length > end {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "PNG chunk beyond the end of the file"));
+        }
+
+        if !PNG_METADATA_CHUNKS.contains(&kind) {
+            splices.keep(offset, length);
+        } else {
+            splices.remove();
+            if kind == *b"eXIf" && !exif_seen && !keep.is_empty() {
+                exif_seen = true;
+                let mut contents = vec![0; (length - 12) as usize];
+                reader.read_exact(&mut contents)?;
+                if let Some(kept) = kept_exif(&contents, keep) {
+                    splices.insert(PngChunk::new(kind, kept.into()).encoder().bytes().to_vec());
+                }
+            }
+        }
+        offset += length;
+        reader.seek(SeekFrom::Start(offset))?;
+        if kind == *b"IEND" {
+            break;
+        }
+    }
+
+    splices.keep(offset, end - offset);
+    Ok(())
+}
+
+/// Puts the parts of in-memory content together.
+fn assemble(data: &[u8], splices: &[Splice]) -> Vec<u8> {
+    let mut assembled = Vec::with_capacity(data.len());
+    for splice in splices {
+        match splice {
+            Splice::Range { offset, length } => assembled.extend_from_slice(&data[*offset as usize..(*offset + *length) as usize]),
+            Splice::Data(bytes) => assembled.extend_from_slice(bytes),
+        }
+    }
+    assembled
+}
+
+/// Keeps the segments needed to show the image: everything but application
+/// segments, except JFIF, ICC profiles and the Adobe color transform, and comments.
+fn is_jpeg_image_segment(marker: u8, contents: &[u8]) -> bool {
+    match marker {
+        markers::APP0 | markers::APP14 => true,
+        markers::APP2 => contents.starts_with(b"ICC_PROFILE\0"),
+        markers::APP1..=markers::APP15 | markers::COM => false,
+        _ => true,
+    }
+}
+
+/// WebP is done by hand, since the `VP8X` flags have to match the chunks present.
+fn strip_webp(webp: &mut WebP, keep: &[MetadataTag]) {
+    // The EXIF chunk holds TIFF data, though some writers put a JPEG style prefix before it
+    let kept = webp
+        .chunk_by_id(CHUNK_EXIF)
+        .and_then(|chunk| chunk.content().data().cloned())
+        .and_then(|exif| kept_exif(exif.strip_prefix(b"Exif\0\0").unwrap_or(&exif), keep));
+    webp.remove_chunks_by_id(CHUNK_EXIF);
+    webp.remove_chunks_by_id(CHUNK_XMP);
+
+    // Only the extended format can carry metadata, so simple files are done
+    let Some(vp8x) = webp.chunks_mut().iter_mut().find(|chunk| chunk.id() == CHUNK_VP8X) else {
+        return;
+    };
+    let Some(mut header) = vp8x.content().data().map(|data| data.to_vec()).filter(|data| !data.is_empty()) else {
+        return;
+    };
+    header[0] &= !(WEBP_EXIF_FLAG | WEBP_XMP_FLAG);
+    if kept.is_some() {
+        header[0] |= WEBP_EXIF_FLAG;
+    }
+    *vp8x = RiffChunk::new(CHUNK_VP8X, RiffContent::Data(header.into()));
+
+    if let Some(exif) = kept {
+        webp.chunks_mut().push(RiffChunk::new(CHUNK_EXIF, RiffContent::Data(exif.into())));
+    }
+}
+
+/// Puts the `keep` tags of the original image into a copy re-encoded from it, which
+/// carries no metadata of its own. JPEG and PNG can hold them; for other formats a
+/// warning says they are lost.
+pub fn carry_over(path: &Path, original: &[u8], encoded: Vec<u8>, content_type: ImageType, keep: &[MetadataTag]) -> Vec<u8> {
+    // The orientation was applied to the pixels when the image was decoded
+    let keep: Vec<MetadataTag> = keep.iter().copied().filter(|tag| *tag != MetadataTag::Orientation).collect();
+    let Ok(exif) = Reader::new().read_from_container(&mut Cursor::new(original)) else {
+        return encoded;
+    };
+    let Some(kept) = kept_exif(exif.buf(), &keep) else {
+        return encoded;
+    };
+
+    let carried = match content_type {
+        ImageType::Jpeg => Jpeg::from_bytes(encoded.clone().into()).ok().map(|mut jpeg| {
+            jpeg.set_exif(Some(kept.into()));
+            jpeg.encoder().bytes().to_vec()
+        }),
+        ImageType::Png => Png::from_bytes(encoded.clone().into()).ok().map(|mut png| {
+            png.set_exif(Some(kept.into()));
+            png.encoder().bytes().to_vec()
+        }),
+        _ => None,
+    };
+    carried.unwrap_or_else(|| {
+        log::warn!("The metadata kept for {} is lost when it is re-encoded as {}", path.display(), content_type.name());
+        encoded
+    })
+}
+
+/// TIFF files are made of the same structure as EXIF data, so they are written again
+/// with only the tags describing the image, and the image data copied as it is.
+///
+/// Only the first IFD can be written again, so files with more images are refused:
+/// for raw photos (DNG, NEF, CR2 and the like) that would upload a small preview.
+fn strip_tiff(data: &[u8], keep: &[MetadataTag]) -> io::Result<Vec<u8>> {
+    let exif = Reader::new().read_raw(data.to_vec()).map_err(io::Error::other)?;
+    let raw = exif.fields().any(|field| field.tag.context() == Context::Tiff && TIFF_RAW_TAGS.contains(&field.tag.number()));
+    if raw || exif.fields().any(|field| field.ifd_num != In::PRIMARY) {
+        return Err(io::Error::other("it holds more than one image, like raw photos and multi-page files do"));
+    }
+
+    let strips = image_data(data, exif.get_field(Tag::StripOffsets, In::PRIMARY), exif.get_field(Tag::StripByteCounts, In::PRIMARY))?;
+    let tiles = image_data(data, exif.get_field(Tag::TileOffsets, In::PRIMARY), exif.get_field(Tag::TileByteCounts, In::PRIMARY))?;
+
+    let mut writer = Writer::new();
+    for field in exif.fields().filter(|field| field.ifd_num == In::PRIMARY) {
+        let Tag(context, number) = field.tag;
+        if (context == Context::Tiff && TIFF_IMAGE_TAGS.contains(&number)) || is_kept(field, keep) {
+            writer.push_field(field);
+        }
+    }
+    match (&strips, &tiles) {
+        (Some(strips), _) => writer.set_strips(strips, In::PRIMARY),
+        (None, Some(tiles)) => writer.set_tiles(tiles, In::PRIMARY),
+        (None, None) => return Err(io::Error::other("no image data found")),
+    }
+
+    let mut stripped = Cursor::new(Vec::new());
+    writer.write(&mut stripped, exif.little_endian()).map_err(io::Error::other)?;
+    Ok(stripped.into_inner())
+}
+
+/// The strips or tiles a TIFF image is stored in.
+fn image_data<'a>(data: &'a [u8], offsets: Option<&Field>, lengths: Option<&Field>) -> io::Result<Option<Vec<&'a [u8]>>> {
+    let (Some(offsets), Some(lengths)) = (offsets, lengths) else {
+        return Ok(None);
+    };
+    let (Some(offsets), Some(lengths)) = (offsets.value.iter_uint(), lengths.value.iter_uint()) else {
+        return Err(io::Error::other("invalid image data offsets"));
+    };
+    offsets
+        .zip(lengths)
+        .map(|(offset, length)| {
+            data.get(offset as usize..offset as usize + length as usize)
+                .ok_or_else(|| io::Error::other("image data beyond the end of the file"))
+        })
+        .collect::<io::Result<Vec<_>>>()
+        .map(Some)
+}
+
+fn is_kept(field: &Field, keep: &[MetadataTag]) -> bool {
+    keep.iter().any(|tag| tag.tags().contains(&field.tag))
+}
+
+/// Builds EXIF data holding only the `keep` tags of `exif`, or `None` when none of them are there.
+fn kept_exif(exif: &[u8], keep: &[MetadataTag]) -> Option<Vec<u8>> {
+    if keep.is_empty() {
+        return None;
+    }
+    // EXIF that cannot be read is dropped entirely, which is the safe way to fail
+    let exif = match Reader::new().read_raw(exif.to_vec()) {
+        Ok(exif) => exif,
+        Err(err) => {
+            log::debug!("Dropping unreadable EXIF data: {}", err);
+            return None;
+        }
+    };
+
+    let mut writer = Writer::new();
+    let mut kept = 0;
+    for field in exif.fields().filter(|field| field.ifd_num == In::PRIMARY && is_kept(field, keep)) {
+        writer.push_field(field);
+        kept += 1;
+    }
+    if kept == 0 {
+        return None;
+    }
+
+    let mut data = Cursor::new(Vec::new());
+    match writer.write(&mut data, exif.little_endian()) {
+        Ok(()) => Some(data.into_inner()),
+        Err(err) => {
+            log::debug!("Dropping EXIF data that cannot be written again: {}", err);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::{Rational, Value};
+    use image::{DynamicImage, ImageFormat, RgbImage};
+
+    /// EXIF data with a GPS position, a camera serial number, an orientation and a copyright.
+    fn exif_with_gps() -> Vec<u8> {
+        let fields = [
+            Field { tag: Tag::Orientation, ifd_num: In::PRIMARY, value: Value::Short(vec![6]) },
+            Field { tag: Tag::Copyright, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"Jo Doe".to_vec()]) },
+            Field { tag: Tag::BodySerialNumber, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"SN1234".to_vec()]) },
+            Field { tag: Tag::GPSLatitudeRef, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"N".to_vec()]) },
+            Field {
+                tag: Tag::GPSLatitude,
+                ifd_num: In::PRIMARY,
+                value: Value::Rational(vec![Rational { num: 52, denom: 1 }, Rational { num: 31, denom: 1 }, Rational { num: 0, denom: 1 }]),
+            },
+        ];
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut data = Cursor::new(Vec::new());
+        writer.write(&mut data, false).unwrap();
+        data.into_inner()
+    }
+
+    fn encode(format: ImageFormat) -> Bytes {
+        let mut data = Vec::new();
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(8, 8, image::Rgb([200, 10, 10])))
+            .write_to(&mut Cursor::new(&mut data), format)
+            .unwrap();
+        data.into()
+    }
+
+    fn sample(content_type: ImageType) -> Vec<u8> {
+        let exif = Bytes::from(exif_with_gps());
+        let mut data = Vec::new();
+        match content_type {
+            ImageType::Jpeg => {
+                let mut jpeg = Jpeg::from_bytes(encode(ImageFormat::Jpeg)).unwrap();
+                jpeg.set_exif(Some(exif));
+                jpeg.encoder().write_to(&mut data).unwrap();
+            }
+            ImageType::Png => {
+                let mut png = Png::from_bytes(encode(ImageFormat::Png)).unwrap();
+                png.set_exif(Some(exif));
+                png.encoder().write_to(&mut data).unwrap();
+            }
+            ImageType::Webp => {
+                // The EXIF chunk is added for the `VP8X` header, then its prefix is dropped
+                let mut webp = WebP::from_bytes(encode(ImageFormat::WebP)).unwrap();
+                webp.set_exif(Some(exif.clone()));
+                let chunk = webp.chunks_mut().iter_mut().find(|chunk| chunk.id() == CHUNK_EXIF).unwrap();
+                *chunk = RiffChunk::new(CHUNK_EXIF, RiffContent::Data(exif));
+                webp.encoder().write_to(&mut data).unwrap();
+            }
+            ImageType::Tiff => data = tiff(&exif, &[]),
+            other => unreachable!("{:?} is not tested", other),
+        }
+        data
+    }
+
+    /// A 1x1 grayscale TIFF image with the EXIF fields and `extra` in the same IFD.
+    fn tiff(exif: &[u8], extra: &[Field]) -> Vec<u8> {
+        let fields = [
+            Field { tag: Tag::ImageWidth, ifd_num: In::PRIMARY, value: Value::Long(vec![1]) },
+            Field { tag: Tag::ImageLength, ifd_num: In::PRIMARY, value: Value::Long(vec![1]) },
+            Field { tag: Tag::BitsPerSample, ifd_num: In::PRIMARY, value: Value::Short(vec![8]) },
+            Field { tag: Tag::Compression, ifd_num: In::PRIMARY, value: Value::Short(vec![1]) },
+            Field { tag: Tag::PhotometricInterpretation, ifd_num: In::PRIMARY, value: Value::Short(vec![1]) },
+            Field { tag: Tag::SamplesPerPixel, ifd_num: In::PRIMARY, value: Value::Short(vec![1]) },
+            Field { tag: Tag::RowsPerStrip, ifd_num: In::PRIMARY, value: Value::Long(vec![1]) },
+        ];
+        let metadata = Reader::new().read_raw(exif.to_vec()).unwrap();
+        let strips: [&[u8]; 1] = [&[0x80]];
+        let mut writer = Writer::new();
+        fields.iter().chain(metadata.fields()).chain(extra).for_each(|field| writer.push_field(field));
+        writer.set_strips(&strips, In::PRIMARY);
+        let mut cursor = Cursor::new(Vec::new());
+        writer.write(&mut cursor, true).unwrap();
+        cursor.into_inner()
+    }
+
+    #[test]
+    fn no_gps_tags_survive() {
+        let keeps: [&[MetadataTag]; 2] = [&[], &[MetadataTag::Orientation, MetadataTag::Copyright]];
+        for content_type in [ImageType::Jpeg, ImageType::Png, ImageType::Webp, ImageType::Tiff] {
+            for keep in keeps {
+                let original = sample(content_type);
+                let before = Reader::new().read_from_container(&mut Cursor::new(&original)).unwrap();
+                assert!(before.get_field(Tag::GPSLatitude, In::PRIMARY).is_some(), "{:?} sample has no GPS", content_type);
+
+                let stripped = strip(Path::new("sample"), original.clone().into(), content_type, keep).unwrap().expect("metadata removed");
+                let context = format!("{:?} keeping {:?}", content_type, keep);
+                assert!(!stripped.windows(6).any(|window| window == b"SN1234"), "serial number left in {}", context);
+                if let Ok(after) = Reader::new().read_from_container(&mut Cursor::new(&stripped)) {
+                    assert!(after.fields().all(|field| field.tag.context() != Context::Gps), "GPS tag left in {}", context);
+                    assert!(after.get_field(Tag::GPSInfoIFDPointer, In::PRIMARY).is_none(), "GPS IFD left in {}", context);
+                    assert!(after.get_field(Tag::BodySerialNumber, In::PRIMARY).is_none(), "serial left in {}", context);
+                    for tag in [Tag::Orientation, Tag::Copyright] {
+                        assert_eq!(after.get_field(tag, In::PRIMARY).is_some(), !keep.is_empty(), "{} in {}", tag, context);
+                    }
+                } else {
+                    assert!(keep.is_empty(), "kept tags lost in {}", context);
+                }
+
+                // The pixels are untouched, so the image still decodes
+                image::load_from_memory(&stripped).unwrap_or_else(|err| panic!("{} does not decode: {}", context, err));
+            }
+        }
+
+        // A raw photo keeps its full image in a SubIFD, which cannot be written again,
+        // so it is refused rather than uploaded as its preview with the GPS position
+        let sub_ifds = Field { tag: Tag(Context::Tiff, 330), ifd_num: In::PRIMARY, value: Value::Long(vec![8]) };
+        let raw = tiff(&exif_with_gps(), &[sub_ifds]);
+        let err = strip(Path::new("sample.dng"), raw.into(), ImageType::Tiff, &[]).expect_err("raw photo stripped to its preview");
+        assert!(err.to_string().contains("more than one image"), "unexpected error: {}", err);
+    }
+
+    #[test]
+    fn keeps_orientation_and_refuses_heif_by_default() {
+        let config: MetadataConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config.keep, [MetadataTag::Orientation]);
+        assert_eq!(MetadataConfig::default().keep, [MetadataTag::Orientation]);
+
+        for content_type in [ImageType::Heic, ImageType::Avif] {
+            let mut payload = Payload::from_stdin("photo".to_string(), b"\0\0\0\x18ftypheic".to_vec());
+            payload.content_type = Some(content_type);
+            let err = strip_payload(&payload, &config).err().expect("HEIF metadata stripped");
+            assert!(err.to_string().contains("--keep-metadata"), "unexpected error: {}", err);
+
+            let keep_all = MetadataConfig { keep_all: true, ..MetadataConfig::default() };
+            assert!(strip_payload(&payload, &keep_all).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn carries_kept_tags_over_to_re_encoded_copies() {
+        let keep = [MetadataTag::Orientation, MetadataTag::Copyright];
+        for (original_type, format, content_type) in
+            [(ImageType::Jpeg, ImageFormat::Png, ImageType::Png), (ImageType::Png, ImageFormat::Jpeg, ImageType::Jpeg)]
+        {
+            let original = sample(original_type);
+            let carried = carry_over(Path::new("sample"), &original, encode(format).to_vec(), content_type, &keep);
+
+            let context = format!("{:?} re-encoded as {:?}", original_type, content_type);
+            let after = Reader::new().read_from_container(&mut Cursor::new(&carried)).unwrap();
+            assert!(after.get_field(Tag::Copyright, In::PRIMARY).is_some(), "copyright lost in {}", context);
+            // It was applied to the pixels, so keeping it would turn the image twice
+            assert!(after.get_field(Tag::Orientation, In::PRIMARY).is_none(), "orientation left in {}", context);
+            assert!(after.fields().all(|field| field.tag.context() != Context::Gps), "GPS tag left in {}", context);
+            image::load_from_memory(&carried).unwrap_or_else(|err| panic!("{} does not decode: {}", context, err));
+        }
+
+        // Nothing to carry over leaves the copy as it is
+        let encoded = encode(ImageFormat::Png).to_vec();
+        let carried = carry_over(Path::new("sample"), &sample(ImageType::Jpeg), encoded.clone(), ImageType::Png, &[MetadataTag::Orientation]);
+        assert_eq!(carried, encoded);
+    }
+}
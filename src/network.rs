@@ -0,0 +1,324 @@
+use crate::auth::{self, Secret, SecretString};
+use crate::error::{self, Error};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use reqwest::{Certificate, Identity, NoProxy, Proxy};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, RootCertStore, SignatureScheme};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The `network` section of the configuration: proxy, TLS, timeouts and connection reuse;
+/// the matching flags take precedence.
+#[derive(Deserialize, Default, Debug, Clone)]
+pub struct NetworkConfig {
+    /// Proxy for all requests: an http://, https://, socks5:// or socks5h:// URL,
+    /// with credentials in the URL if needed; without it `$HTTPS_PROXY` and friends apply
+    pub proxy: Option<String>,
+    /// Hosts reached without the proxy, comma-separated as in `$NO_PROXY`; only
+    /// allowed with `proxy`, as proxies from the environment follow `$NO_PROXY`
+    pub no_proxy: Option<String>,
+    /// PEM file with CA certificates to trust besides the system ones
+    pub ca_bundle: Option<PathBuf>,
+    /// Client certificate for mutual TLS: a PEM file, or a PKCS#12 (.p12, .pfx) file
+    pub client_cert: Option<PathBuf>,
+    /// PEM private key of the client certificate, when it is not in `client_cert` itself
+    pub client_key: Option<PathBuf>,
+    /// Password of a PKCS#12 client certificate
+    pub client_cert_password: Option<Secret>,
+    /// Accept any server certificate; only meant for testing
+    #[serde(default)]
+    pub insecure: bool,
+    /// SHA-256 hashes of the public keys (SPKI) the server may present, as `sha256/<base64>`;
+    /// connections to servers with any other key are refused
+    #[serde(default)]
+    pub spki_pins: Vec<String>,
+    /// Seconds to wait for a connection to be established; 0 waits forever [default: 30]
+    pub connect_timeout: Option<u64>,
+    /// Seconds a whole request, upload included, may take; 0 or unset waits forever
+    pub request_timeout: Option<u64>,
+    /// Seconds to wait for the next bytes of a response; 0 waits forever [default: 120]
+    pub read_idle_timeout: Option<u64>,
+    /// HTTP version to speak
+    #[serde(default)]
+    pub http_version: HttpVersion,
+    /// Seconds between TCP keep-alive probes on open connections
+    pub tcp_keepalive: Option<u64>,
+    /// Seconds an idle connection is kept open for reuse [default: 90]
+    pub pool_idle_timeout: Option<u64>,
+    /// Idle connections kept open per host; 0 opens a new connection for each request
+    pub pool_max_idle_per_host: Option<usize>,
+}
+
+/// Which HTTP version requests are made with.
+#[derive(clap::ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpVersion {
+    /// HTTP/1.1, or HTTP/2 when the server offers it
+    #[default]
+    Auto,
+    /// Only HTTP/1.1
+    Http1,
+    /// Only HTTP/2, without asking first; the server must support it
+    Http2,
+}
+
+/// Used when `connect_timeout` is not set, so an unreachable server does not hang a run.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Used when `read_idle_timeout` is not set, so a server that stops answering does not hang a run.
+const DEFAULT_READ_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Turns a timeout in seconds into a duration, where 0 means none.
+fn timeout(seconds: Option<u64>, default: Option<Duration>) -> Option<Duration> {
+    match seconds {
+        Some(0) => None,
+        Some(seconds) => Some(Duration::from_secs(seconds)),
+        None => default,
+    }
+}
+
+/// Builds the HTTP client for the configured proxy and TLS settings.
+pub fn http_client(config: &NetworkConfig) -> Result<reqwest::Client, Error> {
+    let mut builder = reqwest::Client::builder();
+
+    // Timeouts and connection reuse
+    if let Some(connect_timeout) = timeout(config.connect_timeout, Some(DEFAULT_CONNECT_TIMEOUT)) {
+        builder = builder.connect_timeout(connect_timeout);
+    }
+    if let Some(request_timeout) = timeout(config.request_timeout, None) {
+        builder = builder.timeout(request_timeout);
+    }
+    if let Some(read_idle_timeout) = timeout(config.read_idle_timeout, Some(DEFAULT_READ_IDLE_TIMEOUT)) {
+        builder = builder.read_timeout(read_idle_timeout);
+    }
+    builder = match config.http_version {
+        HttpVersion::Auto => builder,
+        HttpVersion::Http1 => builder.http1_only(),
+        HttpVersion::Http2 => builder.http2_prior_knowledge(),
+    };
+    if let Some(tcp_keepalive) = config.tcp_keepalive {
+        builder = builder.tcp_keepalive(Duration::from_secs(tcp_keepalive));
+    }
+    if let Some(pool_idle_timeout) = config.pool_idle_timeout {
+        builder = builder.pool_idle_timeout(Duration::from_secs(pool_idle_timeout));
+    }
+    if let Some(pool_max_idle_per_host) = config.pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(pool_max_idle_per_host);
+    }
+
+    // Proxies from the environment follow `$NO_PROXY` instead
+    if config.proxy.is_none() && config.no_proxy.is_some() {
+        return Err(Error::Config("network.no_proxy: only applies with network.proxy (--proxy); set $NO_PROXY for proxies from the environment".to_string()));
+    }
+    if let Some(proxy_url) = &config.proxy {
+        log::debug!("Using proxy: {}", auth::redact_url(proxy_url));
+        let proxy = Proxy::all(proxy_url).map_err(|err| Error::Config(format!("network.proxy: {}", err)))?;
+        builder = builder.proxy(proxy.no_proxy(config.no_proxy.as_deref().and_then(NoProxy::from_string)));
+    }
+    if config.insecure {
+        log::warn!("TLS certificates are not verified (insecure); anyone on the network can read and alter requests");
+    }
+
+    // Pinning needs a say in the handshake, which only the rustls backend allows
+    if !config.spki_pins.is_empty() {
+        let tls = pinned_tls_config(config)?;
+        return builder.use_preconfigured_tls(tls).build().map_err(Error::Network);
+    }
+
+    if let Some(path) = &config.ca_bundle {
+        let pem = std::fs::read(path).map_err(|err| Error::io(path, err))?;
+        let certs = Certificate::from_pem_bundle(&pem)
+            .map_err(|err| Error::Config(format!("network.ca_bundle: {}: {}", path.display(), err)))?;
+        log::debug!("Trusting {} extra CA certificate(s) from {}", certs.len(), path.display());
+        for cert in certs {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    if let Some(path) = &config.client_cert {
+        builder = builder.identity(identity(config, path)?);
+    }
+    builder.danger_accept_invalid_certs(config.insecure).build().map_err(Error::Network)
+}
+
+/// Reads the client certificate, telling PEM from PKCS#12 by its content.
+fn identity(config: &NetworkConfig, path: &Path) -> Result<Identity, Error> {
+    let cert = std::fs::read(path).map_err(|err| Error::io(path, err))?;
+    let invalid = |err: reqwest::Error| {
+        Error::Config(format!("network.client_cert: {}: {}", path.display(), error::error_chain(&err)))
+    };
+    if !is_pem(&cert) {
+        let password = config.client_cert_password.as_ref().map(Secret::resolve).transpose()?;
+        return Identity::from_pkcs12_der(&cert, password.as_ref().map_or("", SecretString::expose)).map_err(invalid);
+    }
+
+    // A PEM certificate may carry its key in the same file, but native TLS wants them apart
+    let (cert, key) = split_pem(&cert);
+    let key = match &config.client_key {
+        Some(key_path) => std::fs::read(key_path).map_err(|err| Error::io(key_path, err))?,
+        None if key.is_empty() => {
+            return Err(Error::Config(format!(
+                "network.client_cert: {}: no private key in the file; set client_key (--client-key)",
+                path.display()
+            )))
+        }
+        None => key,
+    };
+    Identity::from_pkcs8_pem(&cert, &key).map_err(invalid)
+}
+
+/// Separates the certificates of a PEM file from its private keys.
+fn split_pem(pem: &[u8]) -> (Vec<u8>, Vec<u8>) {
+    let (mut certs, mut keys) = (Vec::new(), Vec::new());
+    let mut in_key = false;
+    for line in pem.split_inclusive(|&byte| byte == b'\n') {
+        if line.starts_with(b"-----BEGIN ") {
+            in_key = line.windows(11).any(|window| window == b"PRIVATE KEY");
+        }
+        if in_key { &mut keys } else { &mut certs }.extend_from_slice(line);
+    }
+    (certs, keys)
+}
+
+fn is_pem(data: &[u8]) -> bool {
+    data.windows(11).any(|window| window == b"-----BEGIN ")
+}
+
+/// The TLS settings when public keys are pinned: the usual verification with the system
+/// roots and `ca_bundle`, plus a check of the server key against the pins.
+fn pinned_tls_config(config: &NetworkConfig) -> Result<rustls::ClientConfig, Error> {
+    let pins = config
+        .spki_pins
+        .iter()
+        .map(|pin| parse_pin(pin).ok_or_else(|| Error::Config(format!("network.spki_pins: invalid pin `{}`; expected sha256/<base64>", pin))))
+        .collect::<Result<Vec<_>, _>>()?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    // Insecure connections skip the chain, but still have to present a pinned key
+    let verifier = if config.insecure {
+        None
+    } else {
+        let mut roots = RootCertStore::empty();
+        let native = rustls_native_certs::load_native_certs();
+        for err in &native.errors {
+            log::warn!("Could not load a system CA certificate: {}", err);
+        }
+        roots.add_parsable_certificates(native.certs);
+        if let Some(path) = &config.ca_bundle {
+            for cert in CertificateDer::pem_file_iter(path).map_err(|err| pem_error("ca_bundle", path, err))? {
+                let cert = cert.map_err(|err| pem_error("ca_bundle", path, err))?;
+                roots.add(cert).map_err(|err| Error::Config(format!("network.ca_bundle: {}: {}", path.display(), err)))?;
+            }
+        }
+        let verifier = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+            .build()
+            .map_err(|err| Error::Config(format!("network: {}", err)))?;
+        Some(verifier)
+    };
+    let verifier = PinnedVerifier { inner: verifier, pins, provider: provider.clone() };
+
+    let builder = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .expect("the ring provider supports the default protocol versions")
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier));
+    let tls = match &config.client_cert {
+        Some(path) => {
+            let cert = std::fs::read(path).map_err(|err| Error::io(path, err))?;
+            if !is_pem(&cert) {
+                return Err(Error::Config(format!(
+                    "network.client_cert: {}: PKCS#12 certificates cannot be combined with spki_pins; convert it to PEM",
+                    path.display()
+                )));
+            }
+            let chain = CertificateDer::pem_slice_iter(&cert)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| pem_error("client_cert", path, err))?;
+            let key = match &config.client_key {
+                Some(key_path) => PrivateKeyDer::from_pem_file(key_path).map_err(|err| pem_error("client_key", key_path, err))?,
+                None => PrivateKeyDer::from_pem_slice(&cert).map_err(|err| pem_error("client_cert", path, err))?,
+            };
+            builder
+                .with_client_auth_cert(chain, key)
+                .map_err(|err| Error::Config(format!("network.client_cert: {}: {}", path.display(), err)))?
+        }
+        None => builder.with_no_client_auth(),
+    };
+    Ok(tls)
+}
+
+fn pem_error(field: &str, path: &Path, err: rustls::pki_types::pem::Error) -> Error {
+    Error::Config(format!("network.{}: {}: {}", field, path.display(), err))
+}
+
+/// Decodes `sha256/<base64>`; curl's `sha256//<base64>` is accepted as well.
+fn parse_pin(pin: &str) -> Option<[u8; 32]> {
+    let encoded = pin.strip_prefix("sha256/")?;
+    let encoded = encoded.strip_prefix('/').unwrap_or(encoded);
+    STANDARD.decode(encoded).ok()?.try_into().ok()
+}
+
+/// Verifies server certificates as usual, then requires the key of the server's own
+/// certificate to be one of the pins.
+#[derive(Debug)]
+struct PinnedVerifier {
+    /// Chain verification, or `None` when it is turned off by `insecure`
+    inner: Option<Arc<WebPkiServerVerifier>>,
+    pins: Vec<[u8; 32]>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if let Some(inner) = &self.inner {
+            inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        }
+
+        let cert = webpki::EndEntityCert::try_from(end_entity)
+            .map_err(|err| rustls::Error::General(format!("cannot read the server certificate: {}", err)))?;
+        let hash: [u8; 32] = Sha256::digest(cert.subject_public_key_info().as_ref()).into();
+        if !self.pins.contains(&hash) {
+            return Err(rustls::Error::General(format!(
+                "the server key sha256/{} is not one of the pinned keys",
+                STANDARD.encode(hash)
+            )));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
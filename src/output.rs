@@ -0,0 +1,182 @@
+use crate::error::Error;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+use std::convert::Infallible;
+
+/// What the server returns for a successful upload.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UploadResponse {
+    /// Public URL of the uploaded image
+    pub url: String,
+    #[serde(default, deserialize_with = "string_or_number", skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delete_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delete_url: Option<String>,
+    /// Size in bytes as stored by the server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// Any other fields, kept so templates can use them
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl UploadResponse {
+    /// A response that only holds a URL.
+    pub fn from_url(url: String) -> Self {
+        UploadResponse {
+            url,
+            id: None,
+            delete_token: None,
+            delete_url: None,
+            size: None,
+            width: None,
+            height: None,
+            extra: Map::new(),
+        }
+    }
+
+    /// Parses a response body: a JSON object with at least a `url`, or just a bare URL.
+    pub fn parse(body: &str) -> Result<Self, Error> {
+        let body = body.trim();
+        if body.starts_with("http://") || body.starts_with("https://") {
+            return Ok(UploadResponse::from_url(body.to_string()));
+        }
+
+        serde_json::from_str(body).map_err(|err| Error::Parse(format!("{} in {:?}", err, body)))
+    }
+}
+
+/// How each uploaded file is printed on stdout.
+#[derive(clap::ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Only the URL
+    Url,
+    /// The full parsed response as JSON
+    Json,
+    /// A Markdown image: ![name](url)
+    Markdown,
+    /// An HTML <img> tag
+    Html,
+    /// A BBCode [img] tag for forums
+    Bbcode,
+    /// The `--template` string with `{field}` placeholders expanded
+    Template,
+}
+
+/// Settles the output format: the one given, else the template format when a template
+/// is given, else `default`.
+pub fn resolve_format(
+    format: Option<OutputFormat>,
+    template: Option<&str>,
+    default: OutputFormat,
+) -> Result<OutputFormat, Error> {
+    let format = match (format, template) {
+        (Some(format), _) => format,
+        (None, Some(_)) => OutputFormat::Template,
+        (None, None) => default,
+    };
+    if format == OutputFormat::Template && template.is_none() {
+        return Err(Error::Usage("--output template requires --template".to_string()));
+    }
+    Ok(format)
+}
+
+/// Formats an uploaded file; `name` is the local file name, used as alt text.
+pub fn format(response: &UploadResponse, name: &str, format: OutputFormat, template: Option<&str>) -> String {
+    match format {
+        OutputFormat::Url => response.url.clone(),
+        OutputFormat::Json => serde_json::to_string(response).expect("response serializes to JSON"),
+        OutputFormat::Markdown => format!("![{}]({})", name.replace(['[', ']'], ""), response.url),
+        OutputFormat::Html => {
+            let mut tag = format!("<img src=\"{}\" alt=\"{}\"", escape_html(&response.url), escape_html(name));
+            if let (Some(width), Some(height)) = (response.width, response.height) {
+                tag.push_str(&format!(" width=\"{}\" height=\"{}\"", width, height));
+            }
+            tag + ">"
+        }
+        OutputFormat::Bbcode => format!("[img]{}[/img]", response.url),
+        OutputFormat::Template => expand_template(template.unwrap_or("{url}"), response, name),
+    }
+}
+
+/// Replaces `{field}` with the matching response field (`{a.b}` reaches into nested
+/// objects, `{name}` is the local file name). Unknown fields expand to nothing and
+/// `{{`/`}}` produce literal braces.
+fn expand_template(template: &str, response: &UploadResponse, name: &str) -> String {
+    let fields = serde_json::to_value(response).expect("response serializes to JSON");
+    let expanded = expand(template, |key| {
+        if key == "name" && !fields.as_object().is_some_and(|fields| fields.contains_key("name")) {
+            return Ok::<_, Infallible>(Some(name.to_string()));
+        }
+        let value = key.split('.').try_fold(&fields, |value, part| value.get(part));
+        Ok(Some(match value {
+            Some(Value::String(text)) => text.clone(),
+            Some(Value::Null) | None => {
+                log::warn!("Template field {{{}}} is not in the response", key);
+                String::new()
+            }
+            Some(value) => value.to_string(),
+        }))
+    });
+    let Ok(expanded) = expanded;
+    expanded
+}
+
+/// Expands `{key}` placeholders in one pass, so values are never expanded again; shared
+/// by every template. `value` gives the text for a key, or `None` to keep the placeholder
+/// as it is written. `{{`/`}}` produce literal braces, and a lone brace is kept as it is.
+pub fn expand<E>(template: &str, mut value: impl FnMut(&str) -> Result<Option<String>, E>) -> Result<String, E> {
+    let mut output = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find(['{', '}']) {
+        output.push_str(&rest[..start]);
+        let tail = &rest[start..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            output.push_str(&tail[..1]);
+            rest = &tail[2..];
+            continue;
+        }
+
+        match (tail.starts_with('{'), tail.find('}')) {
+            (true, Some(end)) => {
+                match value(&tail[1..end])? {
+                    Some(text) => output.push_str(&text),
+                    None => output.push_str(&tail[..=end]),
+                }
+                rest = &tail[end + 1..];
+            }
+            // A lone brace is kept as it is
+            _ => {
+                output.push_str(&tail[..1]);
+                rest = &tail[1..];
+            }
+        }
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Accepts ids sent either as strings or as numbers.
+fn string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(match Option::<Value>::deserialize(deserializer)? {
+        Some(Value::String(text)) => Some(text),
+        Some(Value::Null) | None => None,
+        Some(value) => Some(value.to_string()),
+    })
+}
@@ -0,0 +1,270 @@
+use indicatif::{HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How upload progress is reported on stderr.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// Human-readable progress lines, suitable for logs
+    Plain,
+    /// Live progress bars
+    Bar,
+    /// One JSON event per line, for wrappers to parse
+    Json,
+    /// No progress output
+    None,
+}
+
+impl ProgressMode {
+    /// Picks the mode to use: the requested one, or bars when stderr is a terminal.
+    pub fn resolve(requested: Option<ProgressMode>) -> ProgressMode {
+        let is_terminal = std::io::stderr().is_terminal();
+        match requested {
+            // Bars are unreadable when redirected, so they switch themselves off
+            Some(ProgressMode::Bar) if !is_terminal => ProgressMode::None,
+            Some(mode) => mode,
+            None if is_terminal => ProgressMode::Bar,
+            None => ProgressMode::None,
+        }
+    }
+}
+
+/// Minimum time between two plain or JSON progress events for the same counter.
+const EVENT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Progress reporting for one run, shared by all uploads.
+pub struct Progress {
+    mode: ProgressMode,
+    bars: MultiProgress,
+    overall: Option<Arc<Counter>>,
+}
+
+/// Progress of a single file; cheap to clone into the body stream.
+#[derive(Clone)]
+pub struct FileProgress {
+    mode: ProgressMode,
+    file: Arc<Counter>,
+    overall: Option<Arc<Counter>>,
+}
+
+/// Bytes sent against a known total, for one file or the whole batch.
+struct Counter {
+    /// File name, or `None` for the batch total
+    name: Option<String>,
+    total: AtomicU64,
+    sent: AtomicU64,
+    started: Instant,
+    last_event: Mutex<Instant>,
+    bar: Option<ProgressBar>,
+}
+
+impl Progress {
+    /// Creates the reporter; the overall counter is only shown when batching several files.
+    pub fn new(mode: ProgressMode, total_bytes: u64, file_count: usize) -> Self {
+        let bars = if mode == ProgressMode::Bar {
+            MultiProgress::with_draw_target(ProgressDrawTarget::stderr())
+        } else {
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+        };
+
+        let overall = (file_count > 1).then(|| {
+            let bar = (mode == ProgressMode::Bar).then(|| {
+                let bar = bars.add(ProgressBar::new(total_bytes));
+                bar.set_style(bar_style("{prefix:.bold} [{bar:30.green/white}] {bytes}/{total_bytes} {bytes_per_sec} ETA {eta}"));
+                bar.set_prefix(format!("{} files", file_count));
+                bar
+            });
+            Arc::new(Counter::new(None, total_bytes, bar))
+        });
+
+        Progress { mode, bars, overall }
+    }
+
+    /// Starts tracking a file of `length` bytes.
+    pub fn start_file(&self, name: &str, length: u64) -> FileProgress {
+        let bar = (self.mode == ProgressMode::Bar).then(|| {
+            let bar = self.bars.add(ProgressBar::new(length));
+            bar.set_style(bar_style("{prefix} [{bar:30.cyan/blue}] {bytes}/{total_bytes} {bytes_per_sec} ETA {eta}"));
+            bar.set_prefix(name.to_string());
+            bar
+        });
+        let file = Arc::new(Counter::new(Some(name.to_string()), length, bar));
+
+        match self.mode {
+            ProgressMode::Plain => eprintln!("{}: uploading {}", name, HumanBytes(length)),
+            ProgressMode::Json => emit_json(serde_json::json!({ "event": "start", "file": name, "total": length })),
+            ProgressMode::Bar | ProgressMode::None => {}
+        }
+
+        FileProgress { mode: self.mode, file, overall: self.overall.clone() }
+    }
+
+    /// Corrects the batch total when a file sends another number of bytes than its
+    /// size on disk, as when it was resized.
+    pub fn correct_total(&self, planned: u64, actual: u64) {
+        if let Some(overall) = &self.overall {
+            overall.total.fetch_add(actual, Ordering::Relaxed);
+            let total = overall.total.fetch_sub(planned, Ordering::Relaxed) - planned;
+            if let Some(bar) = &overall.bar {
+                bar.set_length(total);
+            }
+        }
+    }
+
+    /// Runs `f` with the bars hidden, so printed lines do not tear them.
+    pub fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        self.bars.suspend(f)
+    }
+
+    /// Ends the overall counter once every file is done.
+    pub fn finish(&self) {
+        if let Some(overall) = &self.overall {
+            if let Some(bar) = &overall.bar {
+                bar.finish_and_clear();
+            }
+            overall.report(self.mode, true);
+        }
+    }
+}
+
+impl FileProgress {
+    /// Records `bytes` more bytes handed to the connection.
+    pub fn advance(&self, bytes: u64) {
+        self.file.advance(bytes, self.mode);
+        if let Some(overall) = &self.overall {
+            overall.advance(bytes, self.mode);
+        }
+    }
+
+    /// Starts counting the file again from zero, for a retried upload.
+    pub fn restart(&self) {
+        let sent = self.file.sent.swap(0, Ordering::Relaxed);
+        if let Some(bar) = &self.file.bar {
+            bar.set_position(0);
+        }
+        if let Some(overall) = &self.overall {
+            overall.sent.fetch_sub(sent, Ordering::Relaxed);
+            if let Some(bar) = &overall.bar {
+                bar.dec(sent);
+            }
+        }
+    }
+
+    /// Marks the file as done, successfully or not.
+    pub fn finish(&self, success: bool) {
+        if let Some(bar) = &self.file.bar {
+            bar.finish_and_clear();
+        }
+
+        let name = self.file.name.as_deref().unwrap_or_default();
+        let sent = self.file.sent.load(Ordering::Relaxed);
+        let elapsed = self.file.started.elapsed();
+        match self.mode {
+            ProgressMode::Plain if success => eprintln!(
+                "{}: done, {} in {} ({}/s)",
+                name,
+                HumanBytes(sent),
+                HumanDuration(elapsed),
+                HumanBytes(rate(sent, elapsed))
+            ),
+            ProgressMode::Plain => eprintln!("{}: failed after {}", name, HumanBytes(sent)),
+            ProgressMode::Json => emit_json(serde_json::json!({
+                "event": "finish",
+                "file": name,
+                "ok": success,
+                "sent": sent,
+                "elapsed_secs": elapsed.as_secs_f64(),
+            })),
+            ProgressMode::Bar | ProgressMode::None => {}
+        }
+    }
+}
+
+impl Counter {
+    fn new(name: Option<String>, total: u64, bar: Option<ProgressBar>) -> Self {
+        Counter {
+            name,
+            total: AtomicU64::new(total),
+            sent: AtomicU64::new(0),
+            started: Instant::now(),
+            last_event: Mutex::new(Instant::now()),
+            bar,
+        }
+    }
+
+    fn advance(&self, bytes: u64, mode: ProgressMode) {
+        self.sent.fetch_add(bytes, Ordering::Relaxed);
+        if let Some(bar) = &self.bar {
+            bar.inc(bytes);
+        } else {
+            self.report(mode, false);
+        }
+    }
+
+    /// Emits a plain or JSON progress event, at most once per `EVENT_INTERVAL` unless forced.
+    fn report(&self, mode: ProgressMode, force: bool) {
+        if !matches!(mode, ProgressMode::Plain | ProgressMode::Json) {
+            return;
+        }
+
+        {
+            let mut last_event = self.last_event.lock().unwrap();
+            let now = Instant::now();
+            if !force && now.duration_since(*last_event) < EVENT_INTERVAL {
+                return;
+            }
+            *last_event = now;
+        }
+
+        let total = self.total.load(Ordering::Relaxed);
+        let sent = self.sent.load(Ordering::Relaxed).min(total);
+        let elapsed = self.started.elapsed();
+        let bytes_per_sec = rate(sent, elapsed);
+        let eta = (bytes_per_sec > 0).then(|| Duration::from_secs((total - sent) / bytes_per_sec));
+        let name = self.name.as_deref().unwrap_or("total");
+
+        if mode == ProgressMode::Plain {
+            let percent = (sent * 100).checked_div(total).unwrap_or(100);
+            let eta = eta.map_or_else(|| "unknown".to_string(), |eta| HumanDuration(eta).to_string());
+            eprintln!(
+                "{}: {} / {} ({}%), {}/s, ETA {}",
+                name,
+                HumanBytes(sent),
+                HumanBytes(total),
+                percent,
+                HumanBytes(bytes_per_sec),
+                eta
+            );
+        } else {
+            emit_json(serde_json::json!({
+                "event": if self.name.is_some() { "progress" } else { "overall" },
+                "file": self.name,
+                "sent": sent,
+                "total": total,
+                "bytes_per_sec": bytes_per_sec,
+                "eta_secs": eta.map(|eta| eta.as_secs()),
+            }));
+        }
+    }
+}
+
+fn bar_style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template)
+        .expect("progress bar template is valid")
+        .progress_chars("=> ")
+}
+
+fn rate(bytes: u64, elapsed: Duration) -> u64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 {
+        (bytes as f64 / secs) as u64
+    } else {
+        0
+    }
+}
+
+fn emit_json(event: serde_json::Value) {
+    eprintln!("{}", event);
+}
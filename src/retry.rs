@@ -0,0 +1,183 @@
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+use serde::{Deserialize, Deserializer};
+use std::time::Duration;
+
+/// The `retry` section of the configuration file; every field is optional.
+#[derive(Deserialize, Default, Debug, Clone)]
+pub struct RetryConfig {
+    /// Attempts per file, including the first one
+    pub max_attempts: Option<u32>,
+    /// Delay before the first retry; it doubles on each further retry
+    pub base_delay_ms: Option<u64>,
+    /// Upper bound for any single delay, including one requested by `Retry-After`
+    pub max_delay_ms: Option<u64>,
+    /// Random spread applied to each delay, as a fraction between 0 and 1
+    #[serde(default, deserialize_with = "deserialize_jitter")]
+    pub jitter: Option<f64>,
+    /// Response statuses that are worth retrying
+    pub retry_statuses: Option<Vec<u16>>,
+}
+
+/// When and how long to wait before repeating a failed upload.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: f64,
+    pub retry_statuses: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: 0.2,
+            retry_statuses: vec![429, 502, 503, 504],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Builds the policy from the configuration, falling back to the defaults.
+    pub fn from_config(config: &RetryConfig) -> Self {
+        let defaults = RetryPolicy::default();
+        RetryPolicy {
+            max_attempts: config.max_attempts.unwrap_or(defaults.max_attempts).max(1),
+            base_delay: config.base_delay_ms.map_or(defaults.base_delay, Duration::from_millis),
+            max_delay: config.max_delay_ms.map_or(defaults.max_delay, Duration::from_millis),
+            jitter: config.jitter.unwrap_or(defaults.jitter).clamp(0.0, 1.0),
+            retry_statuses: config.retry_statuses.clone().unwrap_or(defaults.retry_statuses),
+        }
+    }
+
+    /// Returns whether a response with this status should be retried.
+    pub fn retries_status(&self, status: StatusCode) -> bool {
+        self.retry_statuses.contains(&status.as_u16())
+    }
+
+    /// Returns how long to wait after the given failed attempt (counting from 1),
+    /// preferring the server's `Retry-After` over exponential backoff.
+    pub fn delay(&self, attempt: u32, headers: Option<&HeaderMap>) -> Duration {
+        let delay = match headers.and_then(|headers| retry_after(headers, Utc::now())) {
+            Some(retry_after) => retry_after,
+            None => {
+                let backoff = self.base_delay.saturating_mul(2u32.saturating_pow(attempt - 1));
+                let spread = rand::thread_rng().gen_range(-self.jitter..=self.jitter);
+                backoff.mul_f64(1.0 + spread)
+            }
+        };
+        delay.min(self.max_delay)
+    }
+}
+
+/// Parses `--retry-jitter`, which has to be a fraction between 0 and 1.
+pub fn parse_jitter(value: &str) -> Result<f64, String> {
+    let jitter = value.parse::<f64>().map_err(|err| err.to_string())?;
+    check_jitter(jitter)
+}
+
+fn deserialize_jitter<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    Option::<f64>::deserialize(deserializer)?
+        .map(check_jitter)
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+/// Rejects spreads the delay cannot be scaled by, such as NaN.
+fn check_jitter(jitter: f64) -> Result<f64, String> {
+    if (0.0..=1.0).contains(&jitter) {
+        Ok(jitter)
+    } else {
+        Err(format!("{} is not a fraction between 0 and 1", jitter))
+    }
+}
+
+/// Returns whether a request error is likely to go away on its own.
+pub fn is_transient(err: &reqwest::Error) -> bool {
+    err.is_connect() || err.is_timeout() || err.is_request() || err.is_body()
+}
+
+/// Parses a `Retry-After` header, given either in seconds or as an HTTP date.
+fn retry_after(headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    // A date in the past means the retry can happen right away
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&Utc) - now).to_std().unwrap_or(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn retry_after_header(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn reads_retry_after_in_seconds_or_as_a_date() {
+        let now = DateTime::parse_from_rfc2822("Sun, 06 Nov 1994 08:49:37 GMT").unwrap().with_timezone(&Utc);
+        let cases = [
+            ("120", Some(Duration::from_secs(120))),
+            (" 0 ", Some(Duration::ZERO)),
+            ("Sun, 06 Nov 1994 08:51:07 GMT", Some(Duration::from_secs(90))),
+            ("Mon, 07 Nov 1994 08:49:37 GMT", Some(Duration::from_secs(86400))),
+            ("Sun, 06 Nov 1994 08:00:00 GMT", Some(Duration::ZERO)),
+            ("-5", None),
+            ("1.5", None),
+            ("soon", None),
+            ("", None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(retry_after(&retry_after_header(value), now), expected, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn caps_retry_after_at_the_maximum_delay() {
+        let policy = RetryPolicy { max_delay: Duration::from_secs(30), ..RetryPolicy::default() };
+        assert_eq!(policy.delay(1, Some(&retry_after_header("5"))), Duration::from_secs(5));
+        assert_eq!(policy.delay(1, Some(&retry_after_header("3600"))), Duration::from_secs(30));
+        // Garbage falls back to the backoff
+        assert!(policy.delay(1, Some(&retry_after_header("soon"))) < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn keeps_the_backoff_within_the_jitter() {
+        let policy = RetryPolicy { base_delay: Duration::from_millis(1000), jitter: 0.25, ..RetryPolicy::default() };
+        for attempt in 1..=4 {
+            let backoff = 1000 * 2u128.pow(attempt - 1);
+            for _ in 0..100 {
+                let delay = policy.delay(attempt, None).as_millis();
+                assert!((backoff * 3 / 4..=backoff * 5 / 4).contains(&delay), "attempt {}: {} ms", attempt, delay);
+            }
+        }
+
+        let policy = RetryPolicy { jitter: 0.0, ..policy };
+        assert_eq!(policy.delay(3, None), Duration::from_millis(4000));
+        let policy = RetryPolicy { max_delay: Duration::from_millis(1500), ..policy };
+        assert_eq!(policy.delay(3, None), Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn accepts_only_jitter_between_0_and_1() {
+        assert_eq!(parse_jitter("0"), Ok(0.0));
+        assert_eq!(parse_jitter("0.5"), Ok(0.5));
+        assert_eq!(parse_jitter("1"), Ok(1.0));
+        for value in ["-0.1", "1.5", "nan", "inf", "lots"] {
+            assert!(parse_jitter(value).is_err(), "{}", value);
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use crate::client::Payload;
+use crate::error::Error;
+use std::path::Path;
+
+/// How many bytes from the start of a file are looked at.
+const SNIFF_LENGTH: usize = 512;
+
+/// Image formats recognized by their content.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageType {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+    Avif,
+    Bmp,
+    Tiff,
+    Svg,
+    Heic,
+}
+
+impl ImageType {
+    pub fn mime(self) -> &'static str {
+        match self {
+            ImageType::Png => "image/png",
+            ImageType::Jpeg => "image/jpeg",
+            ImageType::Gif => "image/gif",
+            ImageType::Webp => "image/webp",
+            ImageType::Avif => "image/avif",
+            ImageType::Bmp => "image/bmp",
+            ImageType::Tiff => "image/tiff",
+            ImageType::Svg => "image/svg+xml",
+            ImageType::Heic => "image/heic",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ImageType::Png => "PNG",
+            ImageType::Jpeg => "JPEG",
+            ImageType::Gif => "GIF",
+            ImageType::Webp => "WebP",
+            ImageType::Avif => "AVIF",
+            ImageType::Bmp => "BMP",
+            ImageType::Tiff => "TIFF",
+            ImageType::Svg => "SVG",
+            ImageType::Heic => "HEIC",
+        }
+    }
+
+    /// The usual file extension.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageType::Png => "png",
+            ImageType::Jpeg => "jpg",
+            ImageType::Gif => "gif",
+            ImageType::Webp => "webp",
+            ImageType::Avif => "avif",
+            ImageType::Bmp => "bmp",
+            ImageType::Tiff => "tiff",
+            ImageType::Svg => "svg",
+            ImageType::Heic => "heic",
+        }
+    }
+
+    /// The type a file name claims to have.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        Some(match extension.as_str() {
+            "png" => ImageType::Png,
+            "jpg" | "jpeg" | "jpe" | "jfif" => ImageType::Jpeg,
+            "gif" => ImageType::Gif,
+            "webp" => ImageType::Webp,
+            "avif" => ImageType::Avif,
+            "bmp" | "dib" => ImageType::Bmp,
+            "tif" | "tiff" => ImageType::Tiff,
+            "svg" => ImageType::Svg,
+            "heic" | "heif" => ImageType::Heic,
+            _ => return None,
+        })
+    }
+
+    /// Recognizes an image by the first bytes of its content.
+    pub fn detect(header: &[u8]) -> Option<Self> {
+        if header.starts_with(b"\x89PNG\r\n\x1a\n") {
+            return Some(ImageType::Png);
+        }
+        if header.starts_with(b"\xff\xd8\xff") {
+            return Some(ImageType::Jpeg);
+        }
+        if header.starts_with(b"GIF87a") || header.starts_with(b"GIF89a") {
+            return Some(ImageType::Gif);
+        }
+        if header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WEBP") {
+            return Some(ImageType::Webp);
+        }
+        if header.starts_with(b"BM") && header.len() >= 14 {
+            return Some(ImageType::Bmp);
+        }
+        if header.starts_with(b"II*\0") || header.starts_with(b"MM\0*") {
+            return Some(ImageType::Tiff);
+        }
+        if header.get(4..8) == Some(b"ftyp") {
+            return detect_heif_brand(header);
+        }
+        if is_svg(header) {
+            return Some(ImageType::Svg);
+        }
+        None
+    }
+}
+
+/// AVIF and HEIC are both HEIF containers; the `ftyp` box lists brands telling them apart.
+fn detect_heif_brand(header: &[u8]) -> Option<ImageType> {
+    let box_size = u32::from_be_bytes(header.get(0..4)?.try_into().ok()?) as usize;
+    let ftyp = header.get(8..box_size.min(header.len()))?;
+
+    // The major brand, then the minor version, then the compatible brands
+    let brands = ftyp.chunks_exact(4).enumerate().filter(|(index, _)| *index != 1).map(|(_, brand)| brand);
+    let mut heic = false;
+    for brand in brands {
+        match brand {
+            b"avif" | b"avis" => return Some(ImageType::Avif),
+            b"heic" | b"heix" | b"hevc" | b"hevx" | b"heim" | b"heis" | b"mif1" | b"msf1" => heic = true,
+            _ => {}
+        }
+    }
+    heic.then_some(ImageType::Heic)
+}
+
+/// SVG is XML text: after an optional byte order mark, prolog and comments comes `<svg`.
+fn is_svg(header: &[u8]) -> bool {
+    let text = String::from_utf8_lossy(header.strip_prefix(b"\xef\xbb\xbf").unwrap_or(header)).to_lowercase();
+    text.trim_start().starts_with('<') && text.contains("<svg")
+}
+
+/// Describes how the extension of a file disagrees with its content, if it does.
+fn extension_mismatch(path: &Path, detected: Option<ImageType>) -> Option<String> {
+    match (ImageType::from_extension(path), detected) {
+        (Some(claimed), Some(detected)) if claimed != detected => {
+            Some(format!("{} looks like {} data, not {}", path.display(), detected.name(), claimed.name()))
+        }
+        (Some(claimed), None) => Some(format!("{} is named like a {} image but is not one", path.display(), claimed.name())),
+        (None, Some(detected)) if path.extension().is_some() => {
+            Some(format!("{} contains {} data despite its extension", path.display(), detected.name()))
+        }
+        _ => None,
+    }
+}
+
+/// Looks at the start of a file to find out what it is. Warns when the extension says
+/// otherwise, and rejects the file when it is not an allowed type, before anything is sent.
+pub async fn check(payload: &Payload, allow_types: &[ImageType], deny_non_images: bool) -> Result<Option<ImageType>, Error> {
+    let header = payload.head(SNIFF_LENGTH).await?;
+    let path = payload.path();
+
+    let detected = ImageType::detect(&header);
+    log::debug!("Detected {:?} in {:?}, extension says {:?}", detected, path, ImageType::from_extension(path));
+    if let Some(mismatch) = extension_mismatch(path, detected) {
+        log::warn!("{}", mismatch);
+    }
+
+    if !allow_types.is_empty() && !detected.is_some_and(|detected| allow_types.contains(&detected)) {
+        let found = detected.map_or("not a recognized image", ImageType::name);
+        return Err(Error::Usage(format!("rejected by --allow-types: content is {}", found)));
+    }
+    if deny_non_images && detected.is_none() {
+        return Err(Error::Usage("rejected by --deny-non-images: content is not a recognized image".to_string()));
+    }
+    Ok(detected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{Source, Splice};
+
+    /// An `ftyp` box with the given major brand and compatible brands.
+    fn ftyp(major: &[u8; 4], compatible: &[&[u8; 4]]) -> Vec<u8> {
+        let mut data = ((16 + 4 * compatible.len()) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(b"ftyp");
+        data.extend_from_slice(major);
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        for brand in compatible {
+            data.extend_from_slice(*brand);
+        }
+        data
+    }
+
+    #[test]
+    fn detects_images_by_content() {
+        let cases: &[(&[u8], Option<ImageType>)] = &[
+            (b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", Some(ImageType::Png)),
+            (b"\xff\xd8\xff\xe0\0\x10JFIF", Some(ImageType::Jpeg)),
+            (b"GIF87a\x01\0", Some(ImageType::Gif)),
+            (b"GIF89a\x01\0", Some(ImageType::Gif)),
+            (b"RIFF\x24\0\0\0WEBPVP8 ", Some(ImageType::Webp)),
+            (b"BM\x36\0\0\0\0\0\0\0\x36\0\0\0", Some(ImageType::Bmp)),
+            (b"II*\0\x08\0\0\0", Some(ImageType::Tiff)),
+            (b"MM\0*\0\0\0\x08", Some(ImageType::Tiff)),
+            (b"\xef\xbb\xbf<?xml version=\"1.0\"?>\n<!-- drawn -->\n<SVG xmlns=\"http://www.w3.org/2000/svg\">", Some(ImageType::Svg)),
+            (b"<svg viewBox=\"0 0 1 1\"/>", Some(ImageType::Svg)),
+            // Too short for a BMP header, text that is not SVG, a RIFF that is not WebP
+            (b"BM", None),
+            (b"<html><body>svg</body></html>", None),
+            (b"RIFF\x24\0\0\0WAVEfmt ", None),
+            (b"%PDF-1.7", None),
+            (b"", None),
+        ];
+        for (header, expected) in cases {
+            assert_eq!(ImageType::detect(header), *expected, "{:?}", String::from_utf8_lossy(header));
+        }
+    }
+
+    #[test]
+    fn tells_heif_brands_apart() {
+        let cases: &[(Vec<u8>, Option<ImageType>)] = &[
+            (ftyp(b"avif", &[b"mif1", b"miaf"]), Some(ImageType::Avif)),
+            (ftyp(b"avis", &[]), Some(ImageType::Avif)),
+            // A compatible AVIF brand wins over the HEIF ones
+            (ftyp(b"mif1", &[b"avif"]), Some(ImageType::Avif)),
+            (ftyp(b"heic", &[b"mif1"]), Some(ImageType::Heic)),
+            (ftyp(b"heix", &[]), Some(ImageType::Heic)),
+            (ftyp(b"mif1", &[b"heic"]), Some(ImageType::Heic)),
+            (ftyp(b"msf1", &[]), Some(ImageType::Heic)),
+            // Videos are ISO media files too
+            (ftyp(b"isom", &[b"iso2", b"mp41"]), None),
+            (ftyp(b"qt  ", &[]), None),
+        ];
+        for (header, expected) in cases {
+            assert_eq!(ImageType::detect(header), *expected, "{:?}", String::from_utf8_lossy(header));
+        }
+
+        // The minor version is not a brand, and nothing past the box counts
+        let mut header = ftyp(b"isom", &[]);
+        header[12..16].copy_from_slice(b"avif");
+        assert_eq!(ImageType::detect(&header), None);
+        let mut header = ftyp(b"isom", &[]);
+        header.extend_from_slice(b"avif");
+        assert_eq!(ImageType::detect(&header), None);
+    }
+
+    #[test]
+    fn warns_when_the_extension_disagrees() {
+        let cases = [
+            ("photo.png", Some(ImageType::Png), None),
+            ("photo.JPEG", Some(ImageType::Jpeg), None),
+            ("photo.heif", Some(ImageType::Heic), None),
+            ("photo", Some(ImageType::Png), None),
+            ("notes.txt", None, None),
+            ("photo.png", Some(ImageType::Jpeg), Some("photo.png looks like JPEG data, not PNG")),
+            ("photo.gif", None, Some("photo.gif is named like a GIF image but is not one")),
+            ("photo.bin", Some(ImageType::Webp), Some("photo.bin contains WebP data despite its extension")),
+        ];
+        for (path, detected, expected) in cases {
+            assert_eq!(extension_mismatch(Path::new(path), detected).as_deref(), expected, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_types_that_are_not_allowed() {
+        let png = || Payload::from_stdin("image.png".to_string(), b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec());
+        let text = || Payload::from_stdin("notes.txt".to_string(), b"just text".to_vec());
+
+        assert_eq!(check(&png(), &[], false).await.unwrap(), Some(ImageType::Png));
+        assert_eq!(check(&text(), &[], false).await.unwrap(), None);
+        assert_eq!(check(&png(), &[ImageType::Jpeg, ImageType::Png], true).await.unwrap(), Some(ImageType::Png));
+
+        let err = check(&png(), &[ImageType::Jpeg], false).await.expect_err("PNG allowed");
+        assert_eq!(err.to_string(), "rejected by --allow-types: content is PNG");
+        let err = check(&text(), &[ImageType::Jpeg], false).await.expect_err("text allowed");
+        assert_eq!(err.to_string(), "rejected by --allow-types: content is not a recognized image");
+        let err = check(&text(), &[], true).await.expect_err("text allowed");
+        assert_eq!(err.to_string(), "rejected by --deny-non-images: content is not a recognized image");
+    }
+
+    #[tokio::test]
+    async fn sniffs_spliced_content_from_its_parts() {
+        // The file holds a PNG signature in the middle, the splice puts it first
+        let path = std::env::temp_dir().join(format!("anarchic-sniff-test-{}.bin", std::process::id()));
+        let mut data = vec![0; 1000];
+        data.extend_from_slice(b"\x89PNG\r\n\x1a\n");
+        std::fs::write(&path, &data).unwrap();
+        let splices = vec![Splice::Range { offset: 1000, length: 4 }, Splice::Data(b"\r\n".to_vec()), Splice::Range { offset: 1006, length: 2 }];
+        let payload = Payload { file_name: "image.png".to_string(), content_type: None, source: Source::Spliced(path.clone(), splices) };
+
+        let detected = check(&payload, &[], false).await;
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(detected.unwrap(), Some(ImageType::Png));
+    }
+}
@@ -0,0 +1,137 @@
+use crate::client::Payload;
+use crate::error::Error;
+use crate::metadata::{self, MetadataTag};
+use crate::sniff::ImageType;
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use serde::Deserialize;
+use std::io::{self, Cursor};
+
+/// JPEG quality used when none is configured.
+const DEFAULT_JPEG_QUALITY: u8 = 85;
+
+/// AVIF quality used when none is configured.
+const DEFAULT_AVIF_QUALITY: u8 = 70;
+
+/// AVIF encoder speed, from 1 (slowest, smallest) to 10.
+const AVIF_SPEED: u8 = 6;
+
+/// Formats images can be re-encoded to with `--format`.
+#[derive(clap::ValueEnum, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetFormat {
+    Png,
+    Jpeg,
+    /// Written losslessly, so `quality` does not apply
+    Webp,
+    Avif,
+}
+
+impl From<TargetFormat> for ImageType {
+    fn from(format: TargetFormat) -> Self {
+        match format {
+            TargetFormat::Png => ImageType::Png,
+            TargetFormat::Jpeg => ImageType::Jpeg,
+            TargetFormat::Webp => ImageType::Webp,
+            TargetFormat::Avif => ImageType::Avif,
+        }
+    }
+}
+
+/// The `transform` section of the configuration or a profile: how images are
+/// resized and re-encoded before they are uploaded.
+#[derive(Deserialize, Default, Debug, Clone)]
+pub struct TransformConfig {
+    /// Images wider than this are scaled down, keeping their aspect ratio
+    pub max_width: Option<u32>,
+    /// Images taller than this are scaled down, keeping their aspect ratio
+    pub max_height: Option<u32>,
+    /// Quality from 1 to 100 for JPEG and AVIF
+    pub quality: Option<u8>,
+    /// Format to re-encode to [default: keep the format of each file]
+    pub format: Option<TargetFormat>,
+}
+
+impl TransformConfig {
+    pub fn is_active(&self) -> bool {
+        self.max_width.is_some() || self.max_height.is_some() || self.quality.is_some() || self.format.is_some()
+    }
+}
+
+/// Resizes and re-encodes an image as configured, with the `keep` metadata of the original.
+/// Returns the new content and its type, or `None` when it can be uploaded as it is. A file
+/// is only read.
+pub fn apply(payload: &Payload, config: &TransformConfig, keep: &[MetadataTag]) -> Result<Option<(Vec<u8>, ImageType)>, Error> {
+    if !config.is_active() {
+        return Ok(None);
+    }
+    let path = payload.path();
+    let content_type = payload.content_type;
+
+    // Vector images, animations and formats without a decoder are left alone
+    let Some(source_type) = content_type.filter(|content_type| {
+        matches!(
+            content_type,
+            ImageType::Png | ImageType::Jpeg | ImageType::Webp | ImageType::Bmp | ImageType::Tiff
+        )
+    }) else {
+        let kind = content_type.map_or("this kind of file", ImageType::name);
+        log::warn!("Cannot resize or re-encode {}: {} is uploaded as it is", path.display(), kind);
+        return Ok(None);
+    };
+
+    let invalid = |err: image::ImageError| Error::io(path, io::Error::new(io::ErrorKind::InvalidData, err));
+    let data = payload.read()?;
+    let mut decoder = ImageReader::new(Cursor::new(&*data))
+        .with_guessed_format()
+        .map_err(|err| Error::io(path, err))?
+        .into_decoder()
+        .map_err(invalid)?;
+    // Re-encoding drops the EXIF orientation, so it is applied to the pixels instead
+    let orientation = decoder.orientation().map_err(invalid)?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(invalid)?;
+    image.apply_orientation(orientation);
+
+    let max_width = config.max_width.unwrap_or(u32::MAX);
+    let max_height = config.max_height.unwrap_or(u32::MAX);
+    let resize = image.width() > max_width || image.height() > max_height;
+    let target_type = config.format.map_or(source_type, ImageType::from);
+    // Quality only means something to the lossy formats; the others would be written again for nothing
+    let requality = config.quality.is_some() && matches!(target_type, ImageType::Jpeg | ImageType::Avif);
+    if !resize && target_type == source_type && !requality {
+        log::debug!("{} needs no resizing or re-encoding", path.display());
+        return Ok(None);
+    }
+
+    if resize {
+        let (width, height) = (image.width(), image.height());
+        image = image.resize(max_width, max_height, FilterType::Lanczos3);
+        log::debug!("Resized {} from {}x{} to {}x{}", path.display(), width, height, image.width(), image.height());
+    }
+
+    let mut encoded = Vec::new();
+    let result = match target_type {
+        ImageType::Jpeg => {
+            let quality = config.quality.unwrap_or(DEFAULT_JPEG_QUALITY);
+            DynamicImage::ImageRgb8(image.to_rgb8())
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut encoded, quality))
+        }
+        ImageType::Avif => {
+            let quality = config.quality.unwrap_or(DEFAULT_AVIF_QUALITY);
+            DynamicImage::ImageRgba8(image.to_rgba8())
+                .write_with_encoder(AvifEncoder::new_with_speed_quality(&mut encoded, AVIF_SPEED, quality))
+        }
+        ImageType::Png => image.write_to(&mut Cursor::new(&mut encoded), ImageFormat::Png),
+        ImageType::Webp => image.write_to(&mut Cursor::new(&mut encoded), ImageFormat::WebP),
+        ImageType::Bmp => image.write_to(&mut Cursor::new(&mut encoded), ImageFormat::Bmp),
+        ImageType::Tiff => image.write_to(&mut Cursor::new(&mut encoded), ImageFormat::Tiff),
+        other => unreachable!("{:?} is not a target format", other),
+    };
+    result.map_err(|err| Error::io(path, io::Error::other(err)))?;
+
+    let encoded = metadata::carry_over(path, &data, encoded, target_type, keep);
+    log::debug!("Re-encoded {} as {}, {} bytes", path.display(), target_type.name(), encoded.len());
+    Ok(Some((encoded, target_type)))
+}